@@ -1,21 +1,32 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver};
 use std::collections::HashMap;
 use std::path::Path;
+use std::fs::File;
+use std::io::BufReader;
 
-use image::{self, DynamicImage, ImageBuffer};
+use image::{self, AnimationDecoder, DynamicImage, ImageBuffer};
 use dbus::{
     self,
+    message::SignalArgs,
     tree::{self, DataType, Interface, Factory, Tree},
     ffidisp::{Connection, BusType, NameFlag, RequestNameReply},
 };
 
 use chrono::{ DateTime, Utc };
+use serde::{Serialize, Deserialize};
 
 use crate::Config;
 use crate::bus::receiver::BusNotification;
-use crate::bus::dbus_codegen::{org_freedesktop_notifications_server, Value, DBusImage};
-use crate::maths_utility;
+use crate::bus::dbus_codegen::{
+    org_freedesktop_notifications_server, OrgFreedesktopNotificationsActionInvoked,
+    OrgFreedesktopNotificationsNotificationClosed, DBusImage,
+};
+// Hint values, as stored in `Notification::hints`.
+pub use crate::bus::dbus_codegen::Value;
+pub use crate::bus::receiver::INTERNAL_ID;
+use crate::maths_utility::{self, Vec2};
 
 #[derive(Copy, Clone, Default, Debug)]
 struct TData;
@@ -28,7 +39,42 @@ impl DataType for TData {
     type Signal = ();
 }
 
-fn create_iface(sender: mpsc::Sender<Notification>) -> Interface<tree::MTFn<TData>, TData> {
+// Interface for controlling wired itself, e.g. from `wired --replay-last`.  As well as methods, it
+// has:
+//   property `DoNotDisturb` (b, read only) -- whether do not disturb is on.
+//   signal `DoNotDisturbChanged(b enabled)` -- sent whenever do not disturb is turned on or off, so
+//   that e.g. status bars can show it.
+const CONTROL_INTERFACE: &str = "org.wired.Control";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+
+// Frames of animated images with a shorter delay than this are shown for the default delay instead.
+const MIN_FRAME_DELAY_MS: u64 = 20;
+const DEFAULT_FRAME_DELAY_MS: u64 = 100;
+
+// Everything we can receive over dbus.
+#[derive(Clone)]
+pub enum Message {
+    Notify(Notification),
+    // An app asked for one of its notifications to be closed.
+    CloseNotification(u32),
+    // Invoke the default action of the most recently dismissed notification again.
+    ReplayLastAction,
+    // Open or close the notification center.
+    ToggleCenter,
+    // Dismiss the newest notification, or every notification.
+    DismissLast,
+    DismissAll,
+    // Turn do not disturb on or off, or toggle it.
+    SetDnd(bool),
+    ToggleDnd,
+}
+
+// The connection is global so that signals can be emitted from anywhere, the same as `Config`.
+static mut CONNECTION: Option<Connection> = None;
+// Mirrors the manager's do not disturb state, for the `DoNotDisturb` property.
+static DO_NOT_DISTURB: AtomicBool = AtomicBool::new(false);
+
+fn create_iface(sender: mpsc::Sender<Message>) -> Interface<tree::MTFn<TData>, TData> {
     let f = Factory::new_fn();
     org_freedesktop_notifications_server(sender, &f, (), |m| {
         let a: &Arc<BusNotification> = m.path.get_data();
@@ -37,21 +83,74 @@ fn create_iface(sender: mpsc::Sender<Notification>) -> Interface<tree::MTFn<TDat
     })
 }
 
-fn create_tree(iface: Interface<tree::MTFn<TData>, TData>) -> Tree<tree::MTFn<TData>, TData> {
+fn create_control_iface(sender: mpsc::Sender<Message>) -> Interface<tree::MTFn<TData>, TData> {
+    let f = Factory::new_fn();
+    let replay = control_method(&f, "ReplayLastAction", &sender, Message::ReplayLastAction);
+    let toggle_center = control_method(&f, "ToggleCenter", &sender, Message::ToggleCenter);
+    let dismiss_last = control_method(&f, "DismissLast", &sender, Message::DismissLast);
+    let dismiss_all = control_method(&f, "DismissAll", &sender, Message::DismissAll);
+    let dnd_on = control_method(&f, "DndOn", &sender, Message::SetDnd(true));
+    let dnd_off = control_method(&f, "DndOff", &sender, Message::SetDnd(false));
+    let dnd_toggle = control_method(&f, "DndToggle", &sender, Message::ToggleDnd);
+
+    let dnd = f.property::<bool, _>("DoNotDisturb", ())
+        .access(tree::Access::Read)
+        .on_get(|iter, _| {
+            iter.append(DO_NOT_DISTURB.load(Ordering::Relaxed));
+            Ok(())
+        });
+    let dnd_changed = f.signal("DoNotDisturbChanged", ()).sarg::<bool, _>("enabled");
+
+    f.interface(CONTROL_INTERFACE, ())
+        .add_m(replay)
+        .add_m(toggle_center)
+        .add_m(dismiss_last)
+        .add_m(dismiss_all)
+        .add_m(dnd_on)
+        .add_m(dnd_off)
+        .add_m(dnd_toggle)
+        .add_p(dnd)
+        .add_s(dnd_changed)
+}
+
+// A control method which takes no arguments and just passes `message` on to the main loop.
+fn control_method(
+    f: &Factory<tree::MTFn<TData>, TData>,
+    name: &'static str,
+    sender: &mpsc::Sender<Message>,
+    message: Message,
+) -> tree::Method<tree::MTFn<TData>, TData> {
+    let sender = sender.clone();
+    f.method(name, (), move |m| {
+        send_to_main_loop(&sender, message.clone())?;
+        Ok(vec![m.msg.method_return()])
+    })
+}
+
+// Pass a message on to the main loop.  If it's gone (e.g. because we're shutting down), the caller
+// gets an error reply rather than the dbus thread panicking.
+pub fn send_to_main_loop(sender: &mpsc::Sender<Message>, message: Message) -> Result<(), tree::MethodErr> {
+    sender.send(message).map_err(|_| tree::MethodErr::failed(&"wired is shutting down"))
+}
+
+fn create_tree(ifaces: Vec<Interface<tree::MTFn<TData>, TData>>) -> Tree<tree::MTFn<TData>, TData> {
     let n = Arc::new(BusNotification);
 
     let f = Factory::new_fn();
     let mut tree = f.tree(());
-    tree = tree.add(f.object_path("/org/freedesktop/Notifications", n)
-        .introspectable()
-        .add(iface));
+    let mut path = f.object_path(OBJECT_PATH, n).introspectable();
+    for iface in ifaces {
+        path = path.add(iface);
+    }
+    tree = tree.add(path);
 
     tree
 }
 
-pub fn init_bus(sender: mpsc::Sender<Notification>) -> Connection {
-    let iface = create_iface(sender);
-    let tree = create_tree(iface);
+pub fn init_bus(sender: mpsc::Sender<Message>) -> Connection {
+    let iface = create_iface(sender.clone());
+    let control_iface = create_control_iface(sender);
+    let tree = create_tree(vec![iface, control_iface]);
 
     let c = Connection::get_private(BusType::Session).expect("Failed to get a session bus.");
     let reply = c.register_name("org.freedesktop.Notifications", NameFlag::ReplaceExisting as u32)
@@ -71,13 +170,73 @@ pub fn init_bus(sender: mpsc::Sender<Notification>) -> Connection {
     c
 }
 
-pub fn get_connection() -> (Connection, Receiver<Notification>) {
+pub fn get_connection() -> (&'static Connection, Receiver<Message>) {
     let (sender, receiver) = mpsc::channel();
     let c = init_bus(sender);
-    (c, receiver)
+    unsafe {
+        assert!(CONNECTION.is_none());
+        CONNECTION = Some(c);
+        (CONNECTION.as_ref().unwrap(), receiver)
+    }
 }
 
-#[derive(Debug)]
+// Tell applications that one of their notification's actions was invoked.
+// The signal is broadcast, so it doesn't matter if the application has gone away in the meantime.
+pub fn signal_action_invoked(id: u32, action_key: &str) {
+    let signal = OrgFreedesktopNotificationsActionInvoked { id, action_key: action_key.to_owned() };
+    emit(signal.to_emit_message(&OBJECT_PATH.into()));
+}
+
+// Why a notification was closed.  The values are from the spec.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseReason {
+    // Timed out, even if the default action was invoked first (see
+    // `Config::invoke_default_on_expiry`).
+    Expired = 1,
+    // Dismissed by the user.
+    Dismissed = 2,
+    // Closed by a call to `CloseNotification`.
+    Closed = 3,
+    Undefined = 4,
+}
+
+// Tell applications that one of their notifications was closed.
+pub fn signal_notification_closed(id: u32, reason: CloseReason) {
+    let signal = OrgFreedesktopNotificationsNotificationClosed { id, reason: reason as u32 };
+    emit(signal.to_emit_message(&OBJECT_PATH.into()));
+}
+
+// Tell anyone listening (e.g. status bars) that do not disturb was turned on or off.
+pub fn signal_dnd_changed(enabled: bool) {
+    DO_NOT_DISTURB.store(enabled, Ordering::Relaxed);
+    match dbus::Message::new_signal(OBJECT_PATH, CONTROL_INTERFACE, "DoNotDisturbChanged") {
+        Ok(message) => emit(message.append1(enabled)),
+        Err(e) => println!("Couldn't create dbus signal: {}", e),
+    }
+}
+
+fn emit(message: dbus::Message) {
+    // There's no connection when rendering offscreen, and nobody to tell.
+    let connection = match unsafe { CONNECTION.as_ref() } {
+        Some(c) => c,
+        None => return,
+    };
+
+    if connection.send(message).is_err() {
+        println!("Couldn't send dbus signal.");
+    }
+}
+
+// Call a method on the control interface of an already running wired.
+pub fn call_control_method(method: &str) -> Result<(), String> {
+    let c = Connection::get_private(BusType::Session).map_err(|e| e.to_string())?;
+    let message = dbus::Message::new_method_call("org.freedesktop.Notifications", OBJECT_PATH, CONTROL_INTERFACE, method)?;
+    c.send_with_reply_and_block(message, 2000).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Ordered from least to most urgent.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, PartialOrd)]
 pub enum Urgency {
     Low,
     Normal,
@@ -88,7 +247,18 @@ impl Default for Urgency {
     fn default() -> Self { Self::Normal }
 }
 
+// A sound for the notification, from the `sound-file` or `sound-name` hints.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sound {
+    // Path to a sound file.
+    File(String),
+    // Name of a sound from the freedesktop sound theme, e.g. "message-new-instant".
+    Name(String),
+}
+
+#[derive(Clone)]
 pub struct Notification {
+    pub id: u32,
     pub app_name: String,
     pub replaces_id: u32,
 
@@ -96,8 +266,22 @@ pub struct Notification {
     pub body: String,
     pub app_image: Option<DynamicImage>,
     pub hint_image: Option<DynamicImage>,
+    // Every frame of an animated hint image (a GIF from the `image-path` hint), with how long each
+    // one is shown for in milliseconds.  Empty for still images.  `hint_image` is the first frame.
+    pub hint_frames: Vec<(DynamicImage, u64)>,
+
+    // Pairs of (action key, label), in the order the application sent them.
+    pub actions: Vec<(String, String)>,
 
     pub urgency: Urgency,
+    // The `value` hint, which apps like volume controls send to show progress, from 0 to 100.
+    // Some apps send values outside of that range, so it's up to blocks to clamp it.
+    pub value: Option<i32>,
+    // Played when the notification is shown, see `Config::enable_sounds`.  `None` if the app sent
+    // the `suppress-sound` hint.
+    pub sound: Option<Sound>,
+    // Any hints which weren't consumed while building the notification, for blocks to read.
+    pub hints: HashMap<String, Value>,
 
     pub time: DateTime<Utc>,
     pub timeout: i32,
@@ -107,19 +291,21 @@ impl std::fmt::Debug for Notification {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "Notification: {{\n\tapp_name: {}, replaces_id: {}, summary: {}, body: {}, app_image: {}, hint_image: {}, timeout: {}\n}}",
-            self.app_name, self.replaces_id, self.summary, self.body, self.app_image.is_some(), self.hint_image.is_some(), self.timeout,
+            "Notification: {{\n\tid: {}, app_name: {}, replaces_id: {}, summary: {}, body: {}, app_image: {}, hint_image: {}, timeout: {}\n}}",
+            self.id, self.app_name, self.replaces_id, self.summary, self.body, self.app_image.is_some(), self.hint_image.is_some(), self.timeout,
         )
     }
 }
 
 impl Notification {
     pub fn from_dbus(
+        id: u32,
         app_name: &str,
         replaces_id: u32,
         app_icon: &str,
         summary: &str,
         body: &str,
+        actions: Vec<&str>,
         mut hints: HashMap<String, Value>,
         expire_timeout: i32,
     ) -> Self {
@@ -131,15 +317,23 @@ impl Notification {
         // Pango is a bitch about ampersands, and also doesn't decode html entities for us, which
         // applications /love/ to send -- we need to escape ampersands and decode html entities.
         let summary = maths_utility::escape_decode(summary);
-        let body = maths_utility::escape_decode(body);
+        // Bodies are plain text if we haven't said we support markup, see `Config::body_markup`.
+        let body = if Config::get().body_markup {
+            maths_utility::escape_decode(body)
+        } else {
+            maths_utility::escape_markup(body)
+        };
+        // Control characters can break Pango (e.g. NUL), see `Config::control_characters`.
+        let summary = maths_utility::sanitize_control_chars(&summary, &Config::get().control_characters);
+        let body = maths_utility::sanitize_control_chars(&body, &Config::get().control_characters);
 
-        fn image_from_path(path: &str) -> Option<DynamicImage> {
+        fn image_from_path(path: &str) -> Result<DynamicImage, String> {
             let _start = std::time::Instant::now();
             //dbg!("Loading image from path...");
 
             // @TODO: this path shouldn't be active if app_icon is empty?
             let img_path = Path::new(path);
-            let x = image::open(img_path).ok();
+            let x = image::open(img_path).map_err(|e| format!("couldn't open image at \"{}\": {}", path, e));
 
             let _end = std::time::Instant::now();
             //dbg!(end - start);
@@ -147,84 +341,342 @@ impl Notification {
             return x;
         }
 
-        fn image_from_data(dbus_image: DBusImage) -> Option<DynamicImage> {
-            //let start = std::time::Instant::now();
-            //dbg!("Loading image from data...");
-
-            // Sometimes dbus (or the application) can give us junk image data, usually when lots of
-            // stuff is sent at the same time the same time, so we should sanity check the image.
-            // https://github.com/dunst-project/dunst/blob/3f3082efb3724dcd369de78dc94d41190d089acf/src/icon.c#L316
-            let pixelstride = (dbus_image.channels * dbus_image.bits_per_sample + 7)/8;
-            let len_expected = (dbus_image.height - 1) * dbus_image.rowstride + dbus_image.width * pixelstride;
-            let len_actual = dbus_image.data.len() as i32;
-            if len_actual != len_expected {
-                eprintln!(
-                    "Expected image data to be of length: {}, but got a length of {}.",
-                    len_expected, len_actual,
-                );
-                return None;
-            }
-
-            let x = match dbus_image.channels {
-                3 => ImageBuffer::from_raw(dbus_image.width as u32, dbus_image.height as u32, dbus_image.data)
-                        .map(DynamicImage::ImageRgb8),
-                4 => ImageBuffer::from_raw(dbus_image.width as u32, dbus_image.height as u32, dbus_image.data)
-                        .map(DynamicImage::ImageRgba8),
-                _ => {
-                    eprintln!("Unsupported hint image format!  Couldn't load hint image.");
-                    None
-                },
-            };
-
-            //let end = std::time::Instant::now();
-            //dbg!(end - start);
-
-            return x;
+        fn frames_from_gif(path: &str) -> Result<Vec<(DynamicImage, u64)>, String> {
+            let file = File::open(path).map_err(|e| format!("couldn't open image at \"{}\": {}", path, e))?;
+            let frames = image::gif::Decoder::new(BufReader::new(file))
+                .and_then(|decoder| decoder.into_frames().collect_frames())
+                .map_err(|e| format!("couldn't decode animation at \"{}\": {}", path, e))?;
+
+            // Delays are in milliseconds.  Like browsers, we slow down frames with tiny delays,
+            // which were usually meant to be shown at a normal speed.
+            Ok(frames.into_iter()
+                .map(|frame| {
+                    let delay = frame.delay().to_integer() as u64;
+                    let delay = if delay < MIN_FRAME_DELAY_MS { DEFAULT_FRAME_DELAY_MS } else { delay };
+                    (DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+                })
+                .collect())
         }
 
-        let app_image = image_from_path(&app_icon);
+        // Bad hint images shouldn't stop the notification from showing, so we just log the problem
+        // and move on to the next image source.
+        let log_image_error = |result: Result<DynamicImage, String>| {
+            result.map_err(|e| eprintln!("Couldn't load image for notification from \"{}\": {}.", app_name, e)).ok()
+        };
+
+        // `app_icon` is often an icon name rather than a path, so failing to load it isn't worth
+        // reporting.
+        let app_image = image_from_path(&app_icon).ok();
 
-        let hint_image: Option<DynamicImage>;
+        // Try each hint image source in order of preference, falling back to the next one if an
+        // image is missing or fails to load.
         // We want to pass the `dbus_image.data` vec rather than cloning it, so we have to remove it
         // from the array.
         // An alternative might be to put `data` in an option or something like that.
+        let mut hint_image: Option<DynamicImage> = None;
         if let Some(Value::Struct(dbus_image)) = hints.remove("image-data").or(hints.remove("image_data")) {
-            hint_image = image_from_data(dbus_image);
-        } else if let Some(Value::String(path)) = hints.get("image-path").or(hints.get("image_path")) {
-            hint_image = image_from_path(&path);
-        } else if let Some(Value::Struct(dbus_image)) = hints.remove("icon_data") {
-            hint_image = image_from_data(dbus_image);
-        } else {
-            hint_image = None;
+            hint_image = log_image_error(image_from_data(dbus_image));
         }
-
-        let urgency: Urgency;
-        if let Some(Value::U8(level)) = hints.get("urgency") {
-            match level {
-                0 => urgency = Urgency::Low,
-                1 => urgency = Urgency::Normal,
-                2 => urgency = Urgency::Critical,
-                _ => urgency = Urgency::Normal,
+        let mut hint_frames = vec![];
+        if hint_image.is_none() {
+            if let Some(Value::String(path)) = hints.get("image-path").or(hints.get("image_path")) {
+                // Still GIFs are loaded as normal images.
+                if path.to_lowercase().ends_with(".gif") {
+                    match frames_from_gif(&path) {
+                        Ok(frames) if frames.len() > 1 => {
+                            hint_image = Some(frames[0].0.clone());
+                            hint_frames = frames;
+                        },
+                        Ok(_) => {},
+                        Err(e) => eprintln!("Couldn't load image for notification from \"{}\": {}.", app_name, e),
+                    }
+                }
+                if hint_image.is_none() {
+                    hint_image = log_image_error(image_from_path(&path));
+                }
+            }
+        }
+        if hint_image.is_none() {
+            if let Some(Value::Struct(dbus_image)) = hints.remove("icon_data") {
+                hint_image = log_image_error(image_from_data(dbus_image));
             }
-        } else {
-            urgency = Urgency::Normal;
         }
 
+        // Urgency should be a byte, but some applications send it as another integer type.
+        let urgency = match hints.get("urgency").and_then(hint_as_i64) {
+            Some(0) => Urgency::Low,
+            Some(2) => Urgency::Critical,
+            _ => Urgency::Normal,
+        };
+
+        let value = hints.get("value").and_then(hint_as_i64).map(|v| v as i32);
+
+        // The spec says a sound file should be played in preference to a themed sound.
+        let suppress_sound = match hints.get("suppress-sound") {
+            Some(Value::Bool(b)) => *b,
+            _ => false,
+        };
+        let sound = match (hints.get("sound-file"), hints.get("sound-name")) {
+            _ if suppress_sound => None,
+            (Some(Value::String(path)), _) => Some(Sound::File(path.clone())),
+            (_, Some(Value::String(name))) => Some(Sound::Name(name.clone())),
+            _ => None,
+        };
+
+        // Actions are sent as a flat list of alternating keys and labels.
+        let actions: Vec<(String, String)> = actions
+            .chunks_exact(2)
+            .map(|pair| (pair[0].to_owned(), pair[1].to_owned()))
+            .collect();
+
+        // Notifications with actions can have a longer default timeout, so there's time to click
+        // them.  Timeouts chosen by the application are always respected, and -1 means the
+        // application left it up to us.  0 means the notification never expires, which is also
+        // what critical notifications get by default.
         let mut timeout = expire_timeout;
-        if timeout <= 0 {
-            timeout = Config::get().timeout;
+        if timeout < 0 {
+            let cfg = Config::get();
+            timeout = match cfg.timeout_with_actions {
+                _ if urgency == Urgency::Critical => 0,
+                Some(t) if !actions.is_empty() => t,
+                _ => cfg.timeout,
+            };
         }
 
         Self {
+            id,
             app_name: app_name.to_owned(),
             replaces_id,
             summary,
             body,
             app_image,
             hint_image,
+            hint_frames,
+            actions,
             urgency,
+            value,
+            sound,
+            hints,
             time,
             timeout,
         }
     }
+
+    // The `x` and `y` hints ask for the notification to be placed at a specific point on screen.
+    // They are only meaningful together.
+    pub fn get_position_hint(&self) -> Option<Vec2> {
+        match (self.get_hint_i32("x"), self.get_hint_i32("y")) {
+            (Some(x), Some(y)) => Some(Vec2::new(x as f64, y as f64)),
+            _ => None,
+        }
+    }
+
+    // The label of the "default" action, which is what the spec says should happen when the
+    // notification itself is clicked.
+    pub fn get_default_action(&self) -> Option<&str> {
+        self.actions.iter()
+            .find(|(key, _)| key == "default")
+            .map(|(_, label)| label.as_str())
+    }
+
+    // Get an integer hint, regardless of which integer type (or numeric string) it was sent as.
+    pub fn get_hint_i64(&self, name: &str) -> Option<i64> {
+        self.hints.get(name).and_then(hint_as_i64)
+    }
+
+    pub fn get_hint_i32(&self, name: &str) -> Option<i32> {
+        self.get_hint_i64(name).map(|i| i as i32)
+    }
+
+    pub fn get_hint_string(&self, name: &str) -> Option<&str> {
+        match self.hints.get(name) {
+            Some(Value::String(s)) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    // Get any hint as text, for matching against in rules.  Hints which can't be shown as text
+    // (i.e. images) are empty.
+    pub fn get_hint_text(&self, name: &str) -> Option<String> {
+        self.hints.get(name).map(|v| match v {
+            Value::String(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Struct(_) | Value::Unsupported => String::new(),
+            v => hint_as_i64(v).map(|i| i.to_string()).unwrap_or_default(),
+        })
+    }
+
+    // Get a boolean hint.  Integers are true when non-zero, and strings when they're "true".
+    pub fn get_hint_bool(&self, name: &str) -> Option<bool> {
+        match self.hints.get(name) {
+            Some(Value::Bool(b)) => Some(*b),
+            Some(Value::String(s)) => Some(s.trim().eq_ignore_ascii_case("true")),
+            Some(v) => hint_as_i64(v).map(|i| i != 0),
+            None => None,
+        }
+    }
+
+    // Important notifications never time out, see `Config::important_hint`.
+    pub fn is_important(&self) -> bool {
+        self.get_hint_bool(&Config::get().important_hint).unwrap_or(false)
+    }
+
+    // Whether the notification stays until it's closed by the user or the app, either because it's
+    // important or because it has a timeout of 0.
+    pub fn never_expires(&self) -> bool {
+        self.timeout == 0 || self.is_important()
+    }
+}
+
+// Load an image sent as raw data (the `image-data` hint and friends), checking that the data is
+// as long as the image's size says it should be.
+fn image_from_data(dbus_image: DBusImage) -> Result<DynamicImage, String> {
+    //let start = std::time::Instant::now();
+    //dbg!("Loading image from data...");
+
+    if dbus_image.width <= 0 || dbus_image.height <= 0 {
+        return Err(format!("image data has invalid size {}x{}", dbus_image.width, dbus_image.height));
+    }
+
+    // Sometimes dbus (or the application) can give us junk image data, usually when lots of
+    // stuff is sent at the same time the same time, so we should sanity check the image.
+    // https://github.com/dunst-project/dunst/blob/3f3082efb3724dcd369de78dc94d41190d089acf/src/icon.c#L316
+    // This is done in i64, because junk sizes can overflow an i32.
+    let pixelstride = (dbus_image.channels as i64 * dbus_image.bits_per_sample as i64 + 7)/8;
+    let len_expected =
+        (dbus_image.height as i64 - 1) * dbus_image.rowstride as i64 + dbus_image.width as i64 * pixelstride;
+    let len_actual = dbus_image.data.len() as i64;
+    if len_actual != len_expected {
+        return Err(format!(
+            "expected image data to be of length: {}, but got a length of {}",
+            len_expected, len_actual,
+        ));
+    }
+
+    let (width, height) = (dbus_image.width as u32, dbus_image.height as u32);
+    let x = match dbus_image.channels {
+        3 => ImageBuffer::from_raw(width, height, dbus_image.data)
+                .map(DynamicImage::ImageRgb8),
+        4 => ImageBuffer::from_raw(width, height, dbus_image.data)
+                .map(DynamicImage::ImageRgba8),
+        channels => return Err(format!("unsupported image format with {} channels", channels)),
+    };
+
+    //let end = std::time::Instant::now();
+    //dbg!(end - start);
+
+    x.ok_or_else(|| "image data didn't match its dimensions".to_owned())
+}
+
+// Interpret a hint as an integer, regardless of which integer type (or numeric string) it was sent as.
+// Hints of any other type are ignored.
+fn hint_as_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::I32(i) => Some(*i as i64),
+        Value::I64(i) => Some(*i),
+        Value::U8(u) => Some(*u as i64),
+        Value::U32(u) => Some(*u as i64),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{RefArg, Variant};
+    use dbus::message::MessageType;
+    use image::GenericImageView;
+
+    use super::*;
+
+    fn notify_call(summary: &str) -> dbus::Message {
+        let hints: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let mut msg = dbus::Message::new_method_call(
+            "org.freedesktop.Notifications", OBJECT_PATH, "org.freedesktop.Notifications", "Notify",
+        ).unwrap()
+            .append3("app", 0u32, "")
+            .append3(summary, "Body", Vec::<&str>::new())
+            .append2(hints, -1i32);
+        // Replies need a serial to refer to, which is normally set when the message is sent.
+        msg.set_serial(1);
+        msg
+    }
+
+    #[test]
+    fn queued_messages_wait_for_main_loop() {
+        Config::init_for_tests();
+        let (sender, receiver) = mpsc::channel();
+        let tree = create_tree(vec![create_iface(sender.clone()), create_control_iface(sender)]);
+
+        // Nothing is reading from the channel yet, like when a notification arrives during startup.
+        for summary in &["First", "Second"] {
+            let replies = tree.handle(&notify_call(summary)).expect("Notify wasn't handled");
+            assert_eq!(replies[0].msg_type(), MessageType::MethodReturn);
+        }
+
+        // Once the main loop is ready, both are waiting for it, in order.
+        let summaries: Vec<String> = receiver.try_iter()
+            .map(|message| match message {
+                Message::Notify(n) => n.summary,
+                _ => panic!("expected a notification"),
+            })
+            .collect();
+        assert_eq!(summaries, vec!["First", "Second"]);
+    }
+
+    #[test]
+    fn messages_after_main_loop_exits_are_errors() {
+        Config::init_for_tests();
+        let (sender, receiver) = mpsc::channel();
+        let tree = create_tree(vec![create_iface(sender.clone()), create_control_iface(sender)]);
+        drop(receiver);
+
+        let replies = tree.handle(&notify_call("Late")).expect("Notify wasn't handled");
+        assert_eq!(replies[0].msg_type(), MessageType::Error);
+    }
+
+    fn rgba_image(width: i32, height: i32, rowstride: i32, len: usize) -> DBusImage {
+        DBusImage {
+            width,
+            height,
+            rowstride,
+            one_point_two_bit_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            data: vec![255; len],
+        }
+    }
+
+    #[test]
+    fn image_data_loads() {
+        let img = image_from_data(rgba_image(2, 2, 8, 16)).unwrap();
+        assert_eq!(img.dimensions(), (2, 2));
+    }
+
+    #[test]
+    fn truncated_image_data_is_rejected() {
+        assert!(image_from_data(rgba_image(2, 2, 8, 12)).is_err());
+        assert!(image_from_data(rgba_image(2, 2, 8, 0)).is_err());
+        // The length matches, but the rows overlap so there isn't enough data for every pixel.
+        assert!(image_from_data(rgba_image(2, 2, 0, 8)).is_err());
+    }
+
+    #[test]
+    fn junk_image_sizes_are_rejected() {
+        assert!(image_from_data(rgba_image(0, 2, 8, 16)).is_err());
+        assert!(image_from_data(rgba_image(2, -1, 8, 16)).is_err());
+        assert!(image_from_data(rgba_image(i32::MAX, i32::MAX, i32::MAX, 16)).is_err());
+    }
+
+    #[test]
+    fn truncated_image_data_falls_back() {
+        Config::init_for_tests();
+        let mut hints = HashMap::new();
+        hints.insert("image-data".to_owned(), Value::Struct(rgba_image(2, 2, 8, 12)));
+        hints.insert("icon_data".to_owned(), Value::Struct(rgba_image(1, 1, 4, 4)));
+
+        let notification = Notification::from_dbus(1, "app", 0, "", "Summary", "Body", vec![], hints, -1);
+        assert_eq!(notification.hint_image.map(|img| img.dimensions()), Some((1, 1)));
+        assert_eq!(notification.summary, "Summary");
+    }
 }
@@ -5,7 +5,7 @@
 use dbus;
 use dbus::arg;
 use dbus::tree;
-use crate::bus::dbus::Notification;
+use crate::bus::dbus::Message;
 use std::sync::mpsc::Sender;
 use std::collections::HashMap;
 
@@ -30,17 +30,20 @@ use std::collections::HashMap;
 //  'x' - i32,
 //  'y' - i32,
 //  'urgency' - u8
-#[derive(Debug)]
+//  'sender-pid' - i64 (non-standard, but common)
+#[derive(Debug, Clone)]
 pub enum Value {
     String(String),
     I32(i32),
+    I64(i64),
     U8(u8),
+    U32(u32),
     Bool(bool),
     Struct(DBusImage),
     Unsupported,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DBusImage {
     pub width: i32,
     pub height: i32,
@@ -91,17 +94,24 @@ impl<'a> arg::Get<'a> for Value {
         }
         let signature = i.signature();
 
-        match arg_type {
+        // Returning `None` here would make reading the whole hints dict fail, and with it the
+        // notification, so anything we can't make sense of becomes `Value::Unsupported` instead.
+        // Code reading hints already has to deal with the wrong type being sent.
+        let value = match arg_type {
             // This seems ugly, but is definitely the image data struct.
             ArgType::Struct if *signature == *"(iiibiiay)" =>
                 i.recurse(ArgType::Struct).and_then(|mut iter| iter.get::<DBusImage>()).map(Value::Struct),
             ArgType::Boolean => i.get::<bool>().map(Value::Bool),
             ArgType::Byte => i.get::<u8>().map(Value::U8),
             ArgType::Int32 => i.get::<i32>().map(Value::I32),
+            ArgType::Int64 => i.get::<i64>().map(Value::I64),
+            ArgType::UInt32 => i.get::<u32>().map(Value::U32),
             ArgType::String => i.get::<String>().map(Value::String),
             ArgType::Variant => i.recurse(ArgType::Variant).and_then(|mut iter| iter.get()),
             _ => None,
-        }
+        };
+
+        Some(value.unwrap_or(Value::Unsupported))
     }
 }
 
@@ -109,12 +119,12 @@ impl<'a> arg::Get<'a> for Value {
 
 pub trait OrgFreedesktopNotifications {
     fn get_capabilities(&self) -> Result<Vec<String>, tree::MethodErr>;
-    fn notify(&self, sender: Sender<Notification>, app_name: &str, replaces_id: u32, app_icon: &str, summary: &str, body: &str, actions: Vec<&str>, hints: HashMap<String, Value> /*::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>*/, expire_timeout: i32) -> Result<u32, tree::MethodErr>;
-    fn close_notification(&self, id: u32) -> Result<(), tree::MethodErr>;
+    fn notify(&self, sender: Sender<Message>, app_name: &str, replaces_id: u32, app_icon: &str, summary: &str, body: &str, actions: Vec<&str>, hints: HashMap<String, Value> /*::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>*/, expire_timeout: i32) -> Result<u32, tree::MethodErr>;
+    fn close_notification(&self, sender: Sender<Message>, id: u32) -> Result<(), tree::MethodErr>;
     fn get_server_information(&self) -> Result<(String, String, String, String), tree::MethodErr>;
 }
 
-pub fn org_freedesktop_notifications_server<F, T, D>(sender: Sender<Notification>, factory: &tree::Factory<tree::MTFn<D>, D>, data: D::Interface, f: F) -> tree::Interface<tree::MTFn<D>, D>
+pub fn org_freedesktop_notifications_server<F, T, D>(sender: Sender<Message>, factory: &tree::Factory<tree::MTFn<D>, D>, data: D::Interface, f: F) -> tree::Interface<tree::MTFn<D>, D>
 where
     D: tree::DataType,
     D::Method: Default,
@@ -136,6 +146,7 @@ where
     let m = m.out_arg(("capabilities", "as"));
     let i = i.add_m(m);
 
+    let close_sender = sender.clone();
     let fclone = f.clone();
     let h = move |minfo: &tree::MethodInfo<tree::MTFn<D>, D>| {
         let s_clone = sender.clone();
@@ -169,10 +180,11 @@ where
 
     let fclone = f.clone();
     let h = move |minfo: &tree::MethodInfo<tree::MTFn<D>, D>| {
+        let s_clone = close_sender.clone();
         let mut i = minfo.msg.iter_init();
         let id: u32 = i.read()?;
         let d = fclone(minfo);
-        d.close_notification(id)?;
+        d.close_notification(s_clone, id)?;
         let rm = minfo.msg.method_return();
         Ok(vec!(rm))
     };
@@ -261,3 +273,43 @@ impl dbus::message::SignalArgs for OrgFreedesktopNotificationsActionInvoked {
     const NAME: &'static str = "ActionInvoked";
     const INTERFACE: &'static str = "org.freedesktop.Notifications";
 }
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{RefArg, Variant};
+
+    use super::*;
+
+    fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+        Variant(Box::new(value))
+    }
+
+    #[test]
+    fn unknown_hint_types_are_unsupported() {
+        let mut hints: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        // A double where an integer is expected, and types no hint uses.
+        hints.insert("urgency", variant(1.5f64));
+        hints.insert("x-list", variant(vec![1u16, 2, 3]));
+        hints.insert("x-image", variant((1i32, 2i32)));
+        hints.insert("category", variant("device".to_owned()));
+        hints.insert("value", variant(50i32));
+        hints.insert("resident", variant(true));
+        hints.insert("x-nested", variant(variant(7u32)));
+
+        let msg = dbus::Message::new_method_call(
+            "org.freedesktop.Notifications", "/org/freedesktop/Notifications", "org.freedesktop.Notifications", "Notify",
+        ).unwrap().append1(hints);
+        let hints: HashMap<String, Value> = msg.read1().expect("hints with unknown types should still parse");
+
+        for name in &["urgency", "x-list", "x-image"] {
+            match hints.get(*name) {
+                Some(Value::Unsupported) => {},
+                other => panic!("expected hint \"{}\" to be unsupported, got {:?}", name, other),
+            }
+        }
+        match (&hints["category"], &hints["value"], &hints["resident"], &hints["x-nested"]) {
+            (Value::String(s), Value::I32(50), Value::Bool(true), Value::U32(7)) if s == "device" => {},
+            other => panic!("supported hints didn't parse: {:?}", other),
+        }
+    }
+}
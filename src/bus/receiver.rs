@@ -1,62 +1,98 @@
 use std::collections::HashMap;
 use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use dbus::tree;
-use crate::bus::dbus::Notification;
+use crate::bus::dbus::{Notification, Message, send_to_main_loop};
+use crate::config::Config;
 
 use super::dbus_codegen::{ OrgFreedesktopNotifications, Value };
 
+// Returned by `GetServerInformation`.
+const SERVER_NAME: &str = env!("CARGO_PKG_NAME");
+const SERVER_VENDOR: &str = env!("CARGO_PKG_AUTHORS");
+// Version of the Desktop Notifications Specification that we follow.
+const SPEC_VERSION: &str = "1.2";
+
+// Notification ids start at 1, because 0 means "no id" in `replaces_id`.
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+// An id which is never given to an app's notification, for notifications wired makes itself.
+pub const INTERNAL_ID: u32 = u32::MAX;
+
+// Ids wrap around eventually, so skip the ones which mean something else.
+fn next_id() -> u32 {
+    loop {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if id != 0 && id != INTERNAL_ID {
+            return id;
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default, Debug)]
 pub struct BusNotification;
 impl OrgFreedesktopNotifications for BusNotification {
     //type Err = dbus::tree::MethodErr;
-    fn close_notification(&self, _id: u32) -> Result<(), tree::MethodErr> {
-        Ok(())
+    fn close_notification(&self, sender: Sender<Message>, id: u32) -> Result<(), tree::MethodErr> {
+        send_to_main_loop(&sender, Message::CloseNotification(id))
     }
 
+    // Only advertise what we actually do, because apps decide how to format notifications from
+    // this.
     fn get_capabilities(&self) -> Result<Vec<String>, tree::MethodErr> {
-        let capabilities: Vec<String> = vec![
-            "actions".to_string(),
-            "body".to_string(),
-            "body-hyperlinks".to_string(),
-            "body-markup".to_string(),
-            "icon-static".to_string(),
-            "sound".to_string(),
-            "persistence".to_string(),
-            "action-icons".to_string(),
+        let cfg = Config::get();
+        let mut capabilities = vec![
+            // Actions are invoked by clicking the notification or its buttons (`ButtonBlock`).
+            "actions",
+            "body",
+            // Links can be opened with the `notification_url` shortcut.
+            "body-hyperlinks",
+            "icon-static",
+            // Closed notifications are kept in the history and the notification center.
+            "persistence",
         ];
+        if cfg.body_markup {
+            capabilities.push("body-markup");
+        }
+        if cfg.enable_sounds {
+            capabilities.push("sound");
+        }
 
-        Ok(capabilities)
+        Ok(capabilities.into_iter().map(str::to_owned).collect())
     }
 
     fn get_server_information(&self) -> Result<(String, String, String, String), tree::MethodErr> {
         Ok((
-            env!("CARGO_PKG_NAME").to_string(),
-            env!("CARGO_PKG_AUTHORS").to_string(),
-            env!("CARGO_PKG_VERSION").to_string(),
-            "1.2".to_string(),
+            SERVER_NAME.to_owned(),
+            SERVER_VENDOR.to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+            SPEC_VERSION.to_owned(),
         ))
     }
 
     fn notify(
         &self,
-        sender: Sender<Notification>,
+        sender: Sender<Message>,
         app_name: &str,
         replaces_id: u32,
         app_icon: &str,
         summary: &str,
         body: &str,
-        _actions: Vec<&str>,
+        actions: Vec<&str>,
         hints: HashMap<String, Value>,
         expire_timeout: i32,
         ) -> Result<u32, tree::MethodErr> {
 
+        // A replacement keeps the id of the notification it replaces, so that it can be replaced or
+        // closed again using the id the app already has.  Apps can't replace our own notifications.
+        let replaces_id = if replaces_id == INTERNAL_ID { 0 } else { replaces_id };
+        let id = if replaces_id != 0 { replaces_id } else { next_id() };
         let notification = Notification::from_dbus(
-            app_name, replaces_id, app_icon, summary, body, hints, expire_timeout,
+            id, app_name, replaces_id, app_icon, summary, body, actions, hints, expire_timeout,
         );
 
-        sender.send(notification).unwrap();
+        send_to_main_loop(&sender, Message::Notify(notification))?;
 
-        Ok(0 as u32)
+        Ok(id)
     }
 }
@@ -7,17 +7,22 @@ use std::{
     io,
     path::PathBuf,
     fmt::{self, Display, Formatter},
+    collections::HashMap,
 };
 
 use serde::{
+    Serialize,
     Deserialize,
     de::{self, Deserializer, Unexpected},
+    ser::{Serializer, SerializeStruct},
 };
 use notify::{RecommendedWatcher, Watcher, RecursiveMode, DebouncedEvent};
 
 use crate::{
-    maths_utility::{Vec2, Rect},
+    maths_utility::{self, Vec2, Rect, Length},
     rendering::layout::{LayoutBlock, LayoutElement},
+    rules::Rule,
+    bus::dbus::Urgency,
 };
 
 static mut CONFIG: Option<Config> = None;
@@ -69,14 +74,122 @@ pub struct ConfigWatcher {
     pub receiver: Receiver<DebouncedEvent>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub max_notifications: usize,
+    // Move the stack to the other side of the monitor when it would be on the same side as the
+    // focused window, so notifications don't cover what you're working on.  This is checked when
+    // each notification arrives.
+    #[serde(default)]
+    pub avoid_focused_window: bool,
+    // What the root block's hook places notifications against, see `AnchorOrigin`.
+    #[serde(default)]
+    pub anchor_origin: AnchorOrigin,
+    // What happens to notifications that arrive while `max_notifications` are showing.
+    #[serde(default)]
+    pub overflow: Overflow,
 
-    pub timeout: i32,           // Default timeout.
+    // Default timeout, in milliseconds, for notifications which leave it up to us (a timeout of -1).
+    // Notifications with a timeout of 0 never expire.
+    pub timeout: i32,
+    // Default timeout for notifications with actions.  Falls back to `timeout`.
+    pub timeout_with_actions: Option<i32>,
+    // Invoke the default action of notifications which expire without being interacted with, as if
+    // they'd been clicked.  They're still closed with the "expired" reason.  Rules can override this.
+    #[serde(default)]
+    pub invoke_default_on_expiry: bool,
+    // How often windows are updated and redrawn while notifications are showing, in milliseconds.
+    // Lower values make animations (like scrolling text) smoother, higher values use less CPU;
+    // 16 is roughly 60 updates per second.  Clamped to 1-1000.
     pub poll_interval: u64,
+    // How often we check for new notifications when none are showing, in milliseconds.  There's
+    // nothing to animate, so this can be much higher than `poll_interval`, at the cost of new
+    // notifications taking up to this long to appear.  Never lower than `poll_interval`, and
+    // clamped to 1000.
+    #[serde(default = "Config::default_idle_poll_interval")]
+    pub idle_poll_interval: u64,
+
+    // Absolute maximum age of a notification in milliseconds, regardless of its timeout or whether
+    // it's paused.  This is a safety net for misbehaving apps that send notifications which never
+    // expire and never close them.  Entries in the notification center and the "+N more"
+    // notification (see `overflow`) aren't closed by it.
+    // A value of 0 disables this.
+    #[serde(default)]
+    pub max_lifetime: u64,
+    // Longest timeout, in milliseconds, that any notification gets, including ones which would
+    // otherwise never expire (a timeout of 0).  Critical notifications and important ones (see
+    // `important_hint`) aren't capped, and neither is the "+N more" notification.  A value of 0
+    // disables this.
+    #[serde(default)]
+    pub max_timeout: i32,
+    // What `%{remaining}` shows for notifications which never expire.
+    #[serde(default = "Config::default_remaining_placeholder")]
+    pub remaining_placeholder: String,
+
+    // Notifications with this hint set to true never time out, and have to be closed manually.
+    // This is separate from urgency, and ignores timeouts set by the app or by rules.  `max_lifetime`
+    // still applies, so set that to 0 if important notifications should stay forever.
+    #[serde(default = "Config::default_important_hint")]
+    pub important_hint: String,
+
+    // What to do with notifications which would be empty, e.g. because every text block is empty.
+    #[serde(default)]
+    pub empty_notifications: EmptyNotifications,
+
+    // Let critical notifications through while do not disturb is on.
+    #[serde(default)]
+    pub dnd_allow_critical: bool,
+    // Show the notifications that arrived during do not disturb once it's turned off.  Otherwise
+    // they go straight to the history.
+    #[serde(default = "Config::default_dnd_queue")]
+    pub dnd_queue: bool,
+    // The order held back notifications are shown in when they're released all at once.
+    #[serde(default)]
+    pub queue_order: QueueOrder,
+    // Delay between each held back notification appearing when they're released, in milliseconds,
+    // so they don't all appear at once.  0 shows them all at once.
+    #[serde(default)]
+    pub flush_stagger_ms: u64,
+
+    // Notifications can set this hint to a font description (e.g. "Fira Code 11") to draw all of
+    // their text in that font, instead of the fonts set in the layout.
+    #[serde(default = "Config::default_font_hint")]
+    pub font_hint: String,
+
+    // Read notification bodies as Pango markup.  With this off, bodies are shown exactly as they
+    // were sent, and we stop telling apps that we support markup (the `body-markup` capability).
+    #[serde(default = "Config::default_body_markup")]
+    pub body_markup: bool,
+    // Text with invalid markup is normally shown as plain text.  With this on, it isn't shown at all.
+    #[serde(default)]
+    pub strict_markup: bool,
+    // What to do with control characters (other than newlines and tabs) in summaries and bodies.
+    #[serde(default)]
+    pub control_characters: ControlCharacters,
+
+    // How many closed notifications to remember, e.g. for `wired --replay-last`.
+    #[serde(default = "Config::default_history_length")]
+    pub history_length: usize,
 
+    // Moved into `layout` once loaded, so there's nothing to show when printing the config.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub layout_blocks: Vec<LayoutBlock>,
+    // Layouts to use instead of `layout_blocks` for low or critical urgency notifications.  The
+    // position on screen still comes from the root block in `layout_blocks`, so every notification
+    // stacks together.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layout_blocks_low: Vec<LayoutBlock>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layout_blocks_critical: Vec<LayoutBlock>,
+    // Named layouts which rules can pick for a notification (see `RuleOutput::layout`), taking
+    // precedence over the urgency layouts above.  Like those, the position on screen still comes
+    // from `layout_blocks`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub layouts: HashMap<String, Vec<LayoutBlock>>,
+
+    // Rules which change how matching notifications are handled.  See `rules` module.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
 
     // Optional Properties
     // Draws rectangles around elements.
@@ -86,6 +199,10 @@ pub struct Config {
     pub debug_color: Color,
     #[serde(default = "Config::default_debug_color_alt")]
     pub debug_color_alt: Color,
+    // In debug mode, log notifications which take longer than this to lay out or draw, in
+    // milliseconds.
+    #[serde(default = "Config::default_debug_render_threshold_ms")]
+    pub debug_render_threshold_ms: u64,
 
     // Minimum window width and height.  This is used to create the base rect that the notification
     // grows within.
@@ -97,10 +214,169 @@ pub struct Config {
     #[serde(default)]
     pub min_window_height: u32,
 
+    // Space to leave between notifications and each edge of the monitor, e.g. to keep clear of a
+    // bar.  Either a single value for every edge, or `Padding(left: _, right: _, top: _, bottom: _)`.
+    #[serde(default)]
+    pub screen_margin: Padding,
+
+    // When several notifications use `x`/`y` position hints to ask for the same spot, each one is
+    // moved by this much from the last so they don't perfectly cover each other.
+    #[serde(default = "Config::default_position_hint_cascade_offset")]
+    pub position_hint_cascade_offset: Vec2,
+
+    // Whether notification windows accept keyboard focus.
+    // Most window managers will focus newly mapped windows unless told otherwise, which steals
+    // focus from whatever the user is doing, so this is off by default.
+    #[serde(default)]
+    pub focusable: bool,
+
+    // Use a transparent window visual, so that rounded corners and translucent colors show what's
+    // behind the notification.  This needs a compositor; without one, transparent windows are often
+    // drawn as black boxes, so turning this off gives solid, opaque notifications instead.
+    #[serde(default = "Config::default_transparency")]
+    pub transparency: bool,
+
+    // Title notification windows with the app name and summary, rather than just "wired".
+    #[serde(default = "Config::default_notification_window_titles")]
+    pub notification_window_titles: bool,
+
+    // When a notification is closed with a click, try to raise the window of the app that sent it.
+    // The window is found using the `window-id` hint, or the `sender-pid` hint if there isn't one.
+    #[serde(default)]
+    pub raise_source_on_click: bool,
+
+    // Stop continuous animations (scrolling text and the group pulse) while running on battery, to
+    // save power.  Notifications still time out, fade, and keep time tokens up to date as normal.
+    #[serde(default)]
+    pub pause_animations_on_battery: bool,
+
+    // Keep notifications from the same app next to each other in the stack.  The first notification
+    // of each group shows any blocks marked as `group_header`, which can use `%a` (app name) and `%c`
+    // (number of notifications in the group) in their text.
+    #[serde(default)]
+    pub stack_by_app: bool,
+    // Briefly flash the existing notifications of a group when a new one joins it.
+    pub group_pulse_color: Option<Color>,
+    #[serde(default = "Config::default_group_pulse_time_ms")]
+    pub group_pulse_time_ms: u64,
+    // Show notifications which belong together in a single window, see `GroupBy`.
+    #[serde(default)]
+    pub group_by: GroupBy,
+
+    // Show notifications from the same app which arrive within this many milliseconds of each
+    // other on a single card, one below the other, instead of in separate windows.  Each one is
+    // drawn with the blocks below the notification block.  `0` turns this off.
+    #[serde(default)]
+    pub combine_window_ms: u64,
+    // Most notifications shown on one card; once it's full, the next one starts a new card.
+    #[serde(default = "Config::default_max_combined")]
+    pub max_combined: usize,
+
+    // Which way notifications read.  `RightToLeft` mirrors the layout horizontally (so e.g. an
+    // image on the left of the text moves to the right) and right-aligns text.
+    // `Auto` picks `RightToLeft` if the locale's language is written right to left.
+    #[serde(default)]
+    pub layout_direction: LayoutDirection,
+
+    #[serde(default)]
+    pub minimize: MinimizeConfig,
+
+    #[serde(default)]
+    pub center: CenterConfig,
+
+    // Read notifications aloud with a text to speech command, for screen reader users.
+    pub announce: Option<AnnounceConfig>,
+
+    // Play the sound that notifications ask for with the `sound-file` or `sound-name` hints.  Files
+    // are played with `paplay`, and sounds from the sound theme with `canberra-gtk-play`.
+    #[serde(default)]
+    pub enable_sounds: bool,
+
+    // Styling for URLs in text.  Colors default to the color of the text around them.  Links can be
+    // opened by hovering over them and using the `notification_url` shortcut.
+    pub link_color: Option<Color>,
+    pub link_hover_color: Option<Color>,
+    #[serde(default)]
+    pub link_underline: bool,
+
+    // Radius of the notification window's corners.  Anything drawn in the corners is cut off, so
+    // this works best with a matching `border_rounding` on the notification block.  Corners only
+    // look round with `transparency` on.
+    #[serde(default)]
+    pub corner_radius: f64,
+
+    // A soft shadow behind notifications.  The window grows to fit the shadow, so leave room for it
+    // in `gap` and `screen_margin`, or notifications will look further apart.  A blur of 0 turns
+    // the shadow off.
+    #[serde(default = "Config::default_shadow_color")]
+    pub shadow_color: Color,
+    #[serde(default)]
+    pub shadow_offset: Vec2,
+    #[serde(default)]
+    pub shadow_blur: f64,
+
+    // Opacity of notifications once they've been read (hovered), to make unread ones stand out.
+    #[serde(default = "Config::default_read_opacity")]
+    pub read_opacity: f64,
+
+    // Stop notifications from expiring while the cursor is over them.  Animations keep running.
+    #[serde(default = "Config::default_pause_on_hover")]
+    pub pause_on_hover: bool,
+
+    // How long notifications take to fade in when they appear, and fade out before they expire,
+    // in milliseconds.  0 disables fading.  Fading out doesn't make notifications last any longer;
+    // it starts `fade_out_ms` before the timeout.
+    #[serde(default)]
+    pub fade_in_ms: u64,
+    #[serde(default)]
+    pub fade_out_ms: u64,
+
+    // How notifications move into place when they first appear.
+    #[serde(default)]
+    pub spawn_animation: SpawnAnimation,
+    // Where notifications start, relative to their final position, when animated.
+    #[serde(default = "Config::default_spawn_offset")]
+    pub spawn_offset: Vec2,
+    // Spring constants for `SpawnAnimation::Spring`.  Lower damping means more overshoot;
+    // damping of `2 * sqrt(stiffness)` or more won't overshoot at all.
+    #[serde(default = "Config::default_spring_stiffness")]
+    pub spring_stiffness: f64,
+    #[serde(default = "Config::default_spring_damping")]
+    pub spring_damping: f64,
+    // How long notifications take to slide to their new place when the stack changes, e.g. when
+    // a notification above them closes.  `0` moves them instantly.
+    #[serde(default)]
+    pub reflow_ms: u64,
+
     pub shortcuts: ShortcutsConfig,
+    // Command which copies its stdin to the clipboard, for the `notification_copy` shortcut.
+    #[serde(default = "Config::default_copy_command")]
+    pub copy_command: Vec<String>,
+    // Shown over a notification for `copy_feedback_ms` after it's been copied.  Pango markup is
+    // allowed.  An empty string turns this off.
+    #[serde(default = "Config::default_copy_feedback_text")]
+    pub copy_feedback_text: String,
+    #[serde(default = "Config::default_copy_feedback_ms")]
+    pub copy_feedback_ms: u64,
+    // Global keys which invoke an action on the newest notification that has it, as pairs of
+    // (keysym name, action key), e.g. `[("XF86AudioPlay", "play-pause"), ("XF86AudioNext", "next")]`.
+    // Keys are only grabbed while a notification with one of the actions is showing, so they reach
+    // other applications as normal the rest of the time.
+    #[serde(default)]
+    pub media_keys: Vec<(String, String)>,
 
-    #[serde(skip)]
+    // These are worked out when loading, but still printed by `wired --print-config`.
+    #[serde(skip_deserializing)]
     pub layout: Option<LayoutBlock>,
+    #[serde(skip_deserializing)]
+    pub layout_low: Option<LayoutBlock>,
+    #[serde(skip_deserializing)]
+    pub layout_critical: Option<LayoutBlock>,
+    #[serde(skip_deserializing)]
+    pub named_layouts: HashMap<String, LayoutBlock>,
+    // Resolved from `layout_direction`.
+    #[serde(skip_deserializing)]
+    pub rtl: bool,
 }
 
 impl Config {
@@ -112,6 +388,95 @@ impl Config {
         return Color::from_rgba(1.0, 0.0, 0.0, 1.0);
     }
 
+    pub fn default_shadow_color() -> Color {
+        return Color::from_rgba(0.0, 0.0, 0.0, 0.5);
+    }
+
+    pub fn default_read_opacity() -> f64 {
+        return 1.0;
+    }
+
+    pub fn default_copy_command() -> Vec<String> {
+        return vec!["xclip".to_owned(), "-selection".to_owned(), "clipboard".to_owned()];
+    }
+
+    pub fn default_copy_feedback_text() -> String {
+        return "Copied!".to_owned();
+    }
+
+    pub fn default_copy_feedback_ms() -> u64 {
+        return 1000;
+    }
+
+    pub fn default_font_hint() -> String {
+        return "x-wired-font".to_owned();
+    }
+
+    pub fn default_idle_poll_interval() -> u64 {
+        return 100;
+    }
+
+    pub fn default_transparency() -> bool {
+        return true;
+    }
+
+    pub fn default_notification_window_titles() -> bool {
+        return true;
+    }
+
+    pub fn default_debug_render_threshold_ms() -> u64 {
+        // About a frame at 60Hz.
+        return 16;
+    }
+
+    pub fn default_dnd_queue() -> bool {
+        return true;
+    }
+
+    pub fn default_body_markup() -> bool {
+        return true;
+    }
+
+    pub fn default_pause_on_hover() -> bool {
+        return true;
+    }
+
+    pub fn default_group_pulse_time_ms() -> u64 {
+        return 400;
+    }
+
+    pub fn default_max_combined() -> usize {
+        return 5;
+    }
+
+    pub fn default_remaining_placeholder() -> String {
+        return "∞".to_owned();
+    }
+
+    pub fn default_spawn_offset() -> Vec2 {
+        return Vec2::new(0.0, -32.0);
+    }
+
+    pub fn default_spring_stiffness() -> f64 {
+        return 300.0;
+    }
+
+    pub fn default_spring_damping() -> f64 {
+        return 20.0;
+    }
+
+    pub fn default_important_hint() -> String {
+        return "important".to_owned();
+    }
+
+    pub fn default_history_length() -> usize {
+        return 32;
+    }
+
+    pub fn default_position_hint_cascade_offset() -> Vec2 {
+        return Vec2::new(16.0, 16.0);
+    }
+
     // Initialize the config.  This does a two things:
     // - Attempts to locate and load a config file on the machine, and if it can't, then loads the
     // default config.
@@ -181,6 +546,13 @@ impl Config {
         }
     }
 
+    // Tests use the default config, which is loaded once and shared between them.
+    #[cfg(test)]
+    pub fn init_for_tests() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| unsafe { CONFIG = Some(Config::default()) });
+    }
+
     // Attempt to load the config again.
     // If we can, then replace the existing config.
     // If we can't, then do nothing.
@@ -249,12 +621,46 @@ impl Config {
     }
 
     pub fn transform_and_validate(mut config: Config) -> Result<Self, Error> {
+        // A poll interval of 0 would spin, and anything over a second feels broken.
+        config.poll_interval = config.poll_interval.max(1).min(1000);
+        config.idle_poll_interval = config.idle_poll_interval.max(config.poll_interval).min(1000);
+
+        config.rtl = config.layout_direction.is_rtl();
+        let rtl = config.rtl;
+
+        let blocks = std::mem::take(&mut config.layout_blocks);
+        config.layout = Some(Config::build_layout(blocks, rtl)?);
+
+        let blocks = std::mem::take(&mut config.layout_blocks_low);
+        if !blocks.is_empty() {
+            config.layout_low = Some(Config::build_layout(blocks, rtl)?);
+        }
+        let blocks = std::mem::take(&mut config.layout_blocks_critical);
+        if !blocks.is_empty() {
+            config.layout_critical = Some(Config::build_layout(blocks, rtl)?);
+        }
+        for (name, blocks) in std::mem::take(&mut config.layouts) {
+            config.named_layouts.insert(name, Config::build_layout(blocks, rtl)?);
+        }
+
+        let unknown_layout = config.rules.iter()
+            .filter_map(|r| r.output.layout.as_ref())
+            .any(|name| !config.named_layouts.contains_key(name));
+        if unknown_layout {
+            return Err(Error::Validate("A rule uses a layout which isn't in `layouts`!"));
+        }
+
+        Ok(config)
+    }
+
+    // Turn a flat list of layout blocks into a tree, rooted at the first block.
+    fn build_layout(mut blocks: Vec<LayoutBlock>, rtl: bool) -> Result<LayoutBlock, Error> {
         // NOTE: we might actually want to search for the "root" text.
-        if config.layout_blocks.len() == 0 {
+        if blocks.len() == 0 {
             return Err(Error::Validate("Config did not contain any layout blocks!"))
         }
 
-        let mut master_layout = config.layout_blocks.swap_remove(0);
+        let mut master_layout = blocks.swap_remove(0);
 
         // Find children in the vec, and recursively add them to the master layout (and its
         // children) to make a tree.
@@ -272,17 +678,44 @@ impl Config {
             }
         }
 
-        find_and_add_children(&mut master_layout, &mut config.layout_blocks);
+        find_and_add_children(&mut master_layout, &mut blocks);
 
-        match master_layout.params {
-            LayoutElement::NotificationBlock(_) => {
-                config.layout = Some(master_layout);
-                Ok(config)
+        // Mirror the layout once here rather than every time it's used.  The root block's hook
+        // positions the notification on the monitor, so it's left alone.
+        if rtl {
+            fn mirror_children(layout: &mut LayoutBlock) {
+                for child in &mut layout.children {
+                    child.hook.parent_anchor = child.hook.parent_anchor.mirrored();
+                    child.hook.self_anchor = child.hook.self_anchor.mirrored();
+                    child.offset = child.offset.mirrored();
+                    mirror_children(child);
+                }
             }
+
+            mirror_children(&mut master_layout);
+        }
+
+        match master_layout.params {
+            LayoutElement::NotificationBlock(_) => Ok(master_layout),
             _ => Err(Error::Validate("The first LayoutBlock params must be of type NotificationBlock!")),
         }
     }
 
+    // The layout to draw a notification of this urgency with, unless a rule picked one by name.
+    pub fn layout_for(&self, urgency: &Urgency, name: Option<&str>) -> &LayoutBlock {
+        if let Some(layout) = name.and_then(|name| self.named_layouts.get(name)) {
+            return layout;
+        }
+
+        let layout = match urgency {
+            Urgency::Low => self.layout_low.as_ref(),
+            Urgency::Critical => self.layout_critical.as_ref(),
+            Urgency::Normal => None,
+        };
+
+        layout.unwrap_or_else(|| self.layout.as_ref().unwrap())
+    }
+
     // Watch config file for changes, and send message to `Configwatcher` when something
     // happens.
     pub fn watch(mut path: PathBuf) -> Result<ConfigWatcher, Error> {
@@ -325,29 +758,222 @@ impl Default for Config {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ShortcutsConfig {
     pub notification_close: u8,
     pub notification_closeall: u8,
     pub notification_pause: u8,
     pub notification_url: u8,
+    // Shrink the notification to a dot in a corner of the monitor, see `MinimizeConfig`.  Clicking
+    // a minimized notification restores it.  0 means unbound.
+    #[serde(default)]
+    pub notification_minimize: u8,
+    // Copy the notification's summary and body to the clipboard, see `Config::copy_command`.
+    // 0 means unbound.
+    #[serde(default)]
+    pub notification_copy: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum SpawnAnimation {
+    None,
+    // Move from `spawn_offset` into place on a spring, overshooting a little before settling.
+    Spring,
+}
+
+impl Default for SpawnAnimation {
+    fn default() -> Self { SpawnAnimation::None }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnnounceConfig {
+    // Program and arguments to run, e.g. `["spd-say", "%a: %s. %b"]`.  `%a`, `%s`, and `%b` are
+    // replaced with the app name, summary, and body, without markup.  The command isn't run through
+    // a shell, so there's no need to worry about quoting.
+    pub command: Vec<String>,
+    // Only announce notifications at least this urgent.
+    #[serde(default)]
+    pub min_urgency: Urgency,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum EmptyNotifications {
+    // Show them anyway, as just a background.
+    Show,
+    // Close them straight away.
+    Suppress,
+    // Use this text as the summary.
+    Fallback(String),
+}
+
+impl Default for EmptyNotifications {
+    fn default() -> Self { EmptyNotifications::Show }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum QueueOrder {
+    // Oldest first.
+    Fifo,
+    // Newest first.
+    Lifo,
+    // Most urgent first, and oldest first within the same urgency.
+    Priority,
+}
+
+impl Default for QueueOrder {
+    fn default() -> Self { QueueOrder::Fifo }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ControlCharacters {
+    // Remove them.
+    Strip,
+    // Show a symbol for them instead, e.g. "␀" for NUL.
+    Replace,
+    // Leave them as they are.
+    Keep,
+}
+
+impl Default for ControlCharacters {
+    fn default() -> Self { ControlCharacters::Strip }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Overflow {
+    // Show the new notification, and close the oldest.
+    CloseOldest,
+    // Hold the new notification back until there's room, with a "+N more" notification in its
+    // place.
+    Queue,
+}
+
+impl Default for Overflow {
+    fn default() -> Self { Overflow::CloseOldest }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum GroupBy {
+    None,
+    // Notifications from the same app replace each other in one window, which keeps the older ones
+    // behind the newest.  Dismissing it shows the next newest, until there are none left.  Text can
+    // show how many notifications are in the window with `%{count}`.
+    AppName,
+}
+
+impl Default for GroupBy {
+    fn default() -> Self { GroupBy::None }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AnchorOrigin {
+    // Notifications are stacked against the monitor.
+    Monitor,
+    // Each notification is placed against the window that was focused when it arrived, outside of
+    // the stack, so it's shown next to the app that probably sent it.  Percentage offsets on the
+    // root block are of the focused window's size.  Falls back to `Monitor` if no window is
+    // focused.
+    FocusedWindow,
+}
+
+impl Default for AnchorOrigin {
+    fn default() -> Self { AnchorOrigin::Monitor }
+}
+
+// How minimized notifications are shown.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MinimizeConfig {
+    // Which corner of the monitor minimized notifications gather in.
+    pub corner: AnchorPosition,
+    // Diameter of the dot and space between dots.
+    pub size: f64,
+    pub gap: f64,
+    pub color: Color,
+}
+
+impl Default for MinimizeConfig {
+    fn default() -> Self {
+        Self {
+            corner: AnchorPosition::BR,
+            size: 16.0,
+            gap: 4.0,
+            color: Color::from_rgba(0.92, 0.86, 0.7, 1.0),
+        }
+    }
+}
+
+// The notification center, a list of the notifications received this session.  It's opened and
+// closed with `wired --toggle-center`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CenterConfig {
+    // Which corner of the monitor the list starts from.
+    pub corner: AnchorPosition,
+    // Space between entries.
+    pub gap: f64,
+    // Most entries to show, newest first.
+    pub max_entries: usize,
+}
+
+impl Default for CenterConfig {
+    fn default() -> Self {
+        Self {
+            corner: AnchorPosition::TR,
+            gap: 8.0,
+            max_entries: 20,
+        }
+    }
+}
+
+// A soft shadow behind a single block, separate from the window's (see `Config::shadow_blur`).  It
+// doesn't change the block's size.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Shadow {
+    pub color: Color,
+    #[serde(default)]
+    pub offset: Vec2,
+    #[serde(default)]
+    pub blur: f64,
+}
+
+impl Shadow {
+    pub fn draw(&self, ctx: &cairo::Context, rect: &Rect, corner_radius: f64) {
+        maths_utility::cairo_soft_shadow(ctx, rect, corner_radius, &self.offset, self.blur, &self.color);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Padding {
     pub left: f64,
     pub right: f64,
     pub top: f64,
     pub bottom: f64,
+    // Sides are a percentage of the content's size (left/right of its width, top/bottom of its
+    // height), rather than pixels.  Only text blocks support this; other blocks reject relative
+    // padding when the config is loaded (see `deserialize_absolute()`).
+    pub relative: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// The offset of a layout block from where its hook puts it.  Percentages are of the parent block's
+// size, or of the monitor's for the root block, so one layout can work across resolutions.
+#[derive(Debug, Clone, Default)]
 pub struct Offset {
-    pub x: f64,
-    pub y: f64,
+    pub x: Length,
+    pub y: Length,
+}
+
+impl Offset {
+    pub fn resolve(&self, parent_rect: &Rect) -> Vec2 {
+        Vec2::new(self.x.resolve(parent_rect.width()), self.y.resolve(parent_rect.height()))
+    }
+
+    // The same offset, in the opposite horizontal direction.
+    pub fn mirrored(&self) -> Self {
+        Offset { x: self.x.negated(), y: self.y.clone() }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum AnchorPosition {
     ML,
     TL,
@@ -359,6 +985,116 @@ pub enum AnchorPosition {
     BL,
 }
 
+impl AnchorPosition {
+    // The same anchor on the opposite horizontal side.
+    pub fn mirrored(&self) -> Self {
+        match self {
+            AnchorPosition::ML => AnchorPosition::MR,
+            AnchorPosition::TL => AnchorPosition::TR,
+            AnchorPosition::TR => AnchorPosition::TL,
+            AnchorPosition::MR => AnchorPosition::ML,
+            AnchorPosition::BR => AnchorPosition::BL,
+            AnchorPosition::BL => AnchorPosition::BR,
+            AnchorPosition::MT => AnchorPosition::MT,
+            AnchorPosition::MB => AnchorPosition::MB,
+        }
+    }
+
+    // The same anchor on the opposite vertical side.
+    pub fn flipped(&self) -> Self {
+        match self {
+            AnchorPosition::TL => AnchorPosition::BL,
+            AnchorPosition::MT => AnchorPosition::MB,
+            AnchorPosition::TR => AnchorPosition::BR,
+            AnchorPosition::BR => AnchorPosition::TR,
+            AnchorPosition::MB => AnchorPosition::MT,
+            AnchorPosition::BL => AnchorPosition::TL,
+            AnchorPosition::ML => AnchorPosition::ML,
+            AnchorPosition::MR => AnchorPosition::MR,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum LayoutDirection {
+    Auto,
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Default for LayoutDirection {
+    fn default() -> Self { LayoutDirection::Auto }
+}
+
+impl LayoutDirection {
+    pub fn is_rtl(&self) -> bool {
+        match self {
+            LayoutDirection::LeftToRight => false,
+            LayoutDirection::RightToLeft => true,
+            LayoutDirection::Auto => {
+                // Same precedence as setlocale: `LC_ALL`, then `LC_MESSAGES`, then `LANG`.
+                let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+                    .filter_map(|var| env::var(var).ok())
+                    .find(|val| !val.is_empty())
+                    .unwrap_or_default();
+
+                // e.g. "ar_EG.UTF-8" -> "ar".
+                let language = locale.split(|c| c == '_' || c == '.' || c == '@').next().unwrap_or("");
+                ["ar", "he", "fa", "ur", "ps", "yi", "dv", "ckb", "sd", "ug"].contains(&language)
+            },
+        }
+    }
+}
+
+// Cairo compositing operator used when drawing a block.  We need our own type because
+// `cairo::Operator` is not deserializable.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Operator {
+    Over,
+    Source,
+    Add,
+    Saturate,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Xor,
+}
+
+impl Default for Operator {
+    fn default() -> Self { Operator::Over }
+}
+
+impl Operator {
+    pub fn to_cairo(&self) -> cairo::Operator {
+        match self {
+            Operator::Over => cairo::Operator::Over,
+            Operator::Source => cairo::Operator::Source,
+            Operator::Add => cairo::Operator::Add,
+            Operator::Saturate => cairo::Operator::Saturate,
+            Operator::Multiply => cairo::Operator::Multiply,
+            Operator::Screen => cairo::Operator::Screen,
+            Operator::Overlay => cairo::Operator::Overlay,
+            Operator::Darken => cairo::Operator::Darken,
+            Operator::Lighten => cairo::Operator::Lighten,
+            Operator::ColorDodge => cairo::Operator::ColorDodge,
+            Operator::ColorBurn => cairo::Operator::ColorBurn,
+            Operator::HardLight => cairo::Operator::HardLight,
+            Operator::SoftLight => cairo::Operator::SoftLight,
+            Operator::Difference => cairo::Operator::Difference,
+            Operator::Exclusion => cairo::Operator::Exclusion,
+            Operator::Xor => cairo::Operator::Xor,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Color {
     pub r: f64,
@@ -404,6 +1140,23 @@ impl Color {
             return Err(Error::Hexidecimal("Incorrect hexidecimal string length."));
         }
     }
+
+    // Pick a color based on a value, using `(threshold, color)` stops: the color of the lowest stop
+    // that the value is below is used, e.g. `[(50, green), (80, yellow)]` with red as `base` gives
+    // green below 50, yellow below 80, and red otherwise.  Stops can be in any order.
+    // Missing values use `base`.
+    pub fn for_value<'a>(base: &'a Color, stops: &'a [(i32, Color)], value: Option<i32>) -> &'a Color {
+        let value = match value {
+            Some(v) => v,
+            None => return base,
+        };
+
+        stops.iter()
+            .filter(|(threshold, _)| value < *threshold)
+            .min_by_key(|(threshold, _)| *threshold)
+            .map(|(_, color)| color)
+            .unwrap_or(base)
+    }
 }
 
 
@@ -444,9 +1197,107 @@ impl<'de> Deserialize<'de> for Color {
     }
 }
 
+// Colors are written back out in the hex form, which is easier to read than rgba.
+impl Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let channel = |c: f64| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+        let hex = format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            channel(self.a), channel(self.r), channel(self.g), channel(self.b),
+        );
+
+        let mut col = serializer.serialize_struct("Color", 1)?;
+        col.serialize_field("hex", &hex)?;
+        col.end()
+    }
+}
+
+impl Serialize for Padding {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut pad = serializer.serialize_struct("Padding", 4)?;
+        pad.serialize_field("left", &self.left)?;
+        pad.serialize_field("right", &self.right)?;
+        pad.serialize_field("top", &self.top)?;
+        pad.serialize_field("bottom", &self.bottom)?;
+        pad.serialize_field("relative", &self.relative)?;
+        pad.end()
+    }
+}
+
+// Padding can be given as a single value for all sides, e.g. `padding: 5.0`, or per side, e.g.
+// `padding: Padding(left: 5.0, right: 5.0, top: 3.0, bottom: 3.0)`.
+// Relative padding is written as a percentage, e.g. `padding: "10%"`, or per side with
+// `relative: true`, e.g. `Padding(left: 10.0, right: 10.0, top: 5.0, bottom: 5.0, relative: true)`.
+impl<'de> Deserialize<'de> for Padding {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Pad {
+            Uniform(f64),
+            UniformRelative(String),
+            Sides {
+                left: f64,
+                right: f64,
+                top: f64,
+                bottom: f64,
+                #[serde(default)]
+                relative: bool,
+            },
+        }
+
+        let error = || de::Error::custom(
+            "expected a single number, a percentage like \"10%\", or `Padding(left: _, right: _, top: _, bottom: _)`"
+        );
+
+        match Pad::deserialize(deserializer) {
+            Ok(Pad::Uniform(p)) => Ok(Padding::new(p, p, p, p)),
+            Ok(Pad::UniformRelative(s)) => {
+                let p = maths_utility::parse_percentage(&s).ok_or_else(error)?;
+                Ok(Padding { relative: true, ..Padding::new(p, p, p, p) })
+            },
+            Ok(Pad::Sides { left, right, top, bottom, relative }) =>
+                Ok(Padding { relative, ..Padding::new(left, right, top, bottom) }),
+            Err(_) => Err(error()),
+        }
+    }
+}
+
 impl Padding {
     pub fn new(left: f64, right: f64, top: f64, bottom: f64) -> Self {
-        Padding { left, right, top, bottom }
+        Padding { left, right, top, bottom, relative: false }
+    }
+
+    // For blocks which can't `resolve()` padding, so that relative padding is an error rather than
+    // being read as pixels.
+    pub fn deserialize_absolute<'de, D>(deserializer: D) -> Result<Padding, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let padding = Padding::deserialize(deserializer)?;
+        if padding.relative {
+            return Err(de::Error::custom("relative padding is only supported by text blocks"));
+        }
+
+        Ok(padding)
+    }
+
+    // Get the padding in pixels for content of the given size.
+    pub fn resolve(&self, content: &Rect) -> Padding {
+        if !self.relative {
+            return self.clone();
+        }
+
+        let (width, height) = (content.width() / 100.0, content.height() / 100.0);
+        Padding::new(self.left * width, self.right * width, self.top * height, self.bottom * height)
     }
 
     pub fn width(&self) -> f64 {
@@ -455,6 +1306,62 @@ impl Padding {
     pub fn height(&self) -> f64 {
         self.top + self.bottom
     }
+
+    // Grow a rect by this padding on each side, keeping its position.
+    pub fn grow(&self, rect: &Rect) -> Rect {
+        Rect::new(rect.x(), rect.y(), rect.width() + self.width(), rect.height() + self.height())
+    }
+
+    // Shrink a rect by this padding on each side.
+    pub fn shrink(&self, rect: &Rect) -> Rect {
+        Rect::new(
+            rect.x() + self.left,
+            rect.y() + self.top,
+            (rect.width() - self.width()).max(0.0),
+            (rect.height() - self.height()).max(0.0),
+        )
+    }
+}
+
+// Offsets are still written `Vec2(x: _, y: _)`, but either side can be a percentage, e.g.
+// `Vec2(x: "5%", y: 7.0)`.  A single percentage like `"10%"` is used for both sides.
+impl Serialize for Offset {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut offset = serializer.serialize_struct("Vec2", 2)?;
+        offset.serialize_field("x", &self.x)?;
+        offset.serialize_field("y", &self.y)?;
+        offset.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Offset {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Off {
+            Uniform(String),
+            Sides { x: Length, y: Length },
+        }
+
+        let error = || de::Error::custom(
+            "expected a percentage like \"10%\", or `Vec2(x: _, y: _)` with numbers or percentages"
+        );
+
+        match Off::deserialize(deserializer) {
+            Ok(Off::Uniform(s)) => {
+                let pc = maths_utility::parse_percentage(&s).ok_or_else(error)?;
+                Ok(Offset { x: Length::Percent(pc), y: Length::Percent(pc) })
+            },
+            Ok(Off::Sides { x, y }) => Ok(Offset { x, y }),
+            Err(_) => Err(error()),
+        }
+    }
 }
 
 impl AnchorPosition {
@@ -471,3 +1378,89 @@ impl AnchorPosition {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What a text block does: resolve against the text's size (after minimums), then grow by it.
+    fn padded(padding: &Padding, content: &Rect) -> Rect {
+        padding.resolve(content).grow(content)
+    }
+
+    // Colors are told apart by their red channel.
+    fn for_value(stops: &[(i32, Color)], value: Option<i32>) -> f64 {
+        Color::for_value(&Color::from_rgba(1.0, 0.0, 0.0, 1.0), stops, value).r
+    }
+
+    fn stops(thresholds: &[i32]) -> Vec<(i32, Color)> {
+        thresholds.iter().map(|&t| (t, Color::from_rgba(t as f64 / 100.0, 0.0, 0.0, 1.0))).collect()
+    }
+
+    #[test]
+    fn color_for_value() {
+        let stops = stops(&[50, 80]);
+        // Below the first stop.
+        assert_eq!(for_value(&stops, Some(-10)), 0.5);
+        assert_eq!(for_value(&stops, Some(49)), 0.5);
+        // Exactly on a stop counts as above it.
+        assert_eq!(for_value(&stops, Some(50)), 0.8);
+        assert_eq!(for_value(&stops, Some(79)), 0.8);
+        // Above the last stop.
+        assert_eq!(for_value(&stops, Some(80)), 1.0);
+        assert_eq!(for_value(&stops, Some(150)), 1.0);
+    }
+
+    #[test]
+    fn color_for_value_unsorted_stops() {
+        let stops = stops(&[80, 20, 50]);
+        assert_eq!(for_value(&stops, Some(10)), 0.2);
+        assert_eq!(for_value(&stops, Some(30)), 0.5);
+        assert_eq!(for_value(&stops, Some(60)), 0.8);
+        assert_eq!(for_value(&stops, Some(90)), 1.0);
+    }
+
+    #[test]
+    fn color_for_missing_value() {
+        assert_eq!(for_value(&stops(&[50, 80]), None), 1.0);
+        assert_eq!(for_value(&[], Some(10)), 1.0);
+    }
+
+    #[test]
+    fn absolute_padding_ignores_content_size() {
+        let padding = Padding::new(5.0, 5.0, 3.0, 3.0);
+        assert_eq!(padded(&padding, &Rect::new(0.0, 0.0, 100.0, 20.0)), Rect::new(0.0, 0.0, 110.0, 26.0));
+        assert_eq!(padded(&padding, &Rect::new(0.0, 0.0, 300.0, 60.0)), Rect::new(0.0, 0.0, 310.0, 66.0));
+    }
+
+    #[test]
+    fn relative_padding_follows_content_size() {
+        let padding: Padding = ron::de::from_str("\"10%\"").unwrap();
+        assert!(padding.relative);
+
+        // Content at its minimum size, then at its maximum.
+        assert_eq!(padded(&padding, &Rect::new(0.0, 0.0, 100.0, 20.0)), Rect::new(0.0, 0.0, 120.0, 24.0));
+        assert_eq!(padded(&padding, &Rect::new(0.0, 0.0, 300.0, 60.0)), Rect::new(0.0, 0.0, 360.0, 72.0));
+    }
+
+    #[test]
+    fn relative_padding_per_side() {
+        let padding: Padding =
+            ron::de::from_str("(left: 10.0, right: 0.0, top: 50.0, bottom: 0.0, relative: true)").unwrap();
+        let resolved = padding.resolve(&Rect::new(0.0, 0.0, 200.0, 40.0));
+        assert_eq!((resolved.left, resolved.right, resolved.top, resolved.bottom), (20.0, 0.0, 20.0, 0.0));
+        assert!(!resolved.relative);
+    }
+
+    #[test]
+    fn relative_padding_rejected_where_unsupported() {
+        #[derive(Deserialize)]
+        struct Block {
+            #[serde(deserialize_with = "Padding::deserialize_absolute")]
+            padding: Padding,
+        }
+
+        assert!(ron::de::from_str::<Block>("(padding: \"10%\")").is_err());
+        assert_eq!(ron::de::from_str::<Block>("(padding: 4.0)").unwrap().padding.width(), 8.0);
+    }
+}
@@ -6,11 +6,15 @@ mod management;
 mod bus;
 mod config;
 mod maths_utility;
+mod x11_utility;
+mod power_utility;
+mod rules;
 
 use std::time::{Instant, Duration};
+use std::path::Path;
 
 use winit::{
-    event::{StartCause, Event, WindowEvent},
+    event::{StartCause, Event, WindowEvent, DeviceEvent, ElementState},
     event_loop::{ControlFlow, EventLoop},
     platform::desktop::EventLoopExtDesktop,
     platform::unix::EventLoopExtUnix,
@@ -20,21 +24,79 @@ use dbus::message::MessageType;
 
 use config::Config;
 use management::NotifyWindowManager;
+use bus::dbus::Message;
 use wired_derive;
 
 fn main() {
     // Hack to avoid winit dpi scaling -- we just want pixels.
     std::env::set_var("WINIT_X11_SCALE_FACTOR", "1.0");
 
+    // Commands for an already running wired, which don't need anything set up.
+    let args: Vec<String> = std::env::args().collect();
+    let control_methods = [
+        ("--replay-last", "ReplayLastAction"),
+        ("--toggle-center", "ToggleCenter"),
+        ("--dismiss-last", "DismissLast"),
+        ("--dismiss-all", "DismissAll"),
+        ("--dnd-on", "DndOn"),
+        ("--dnd-off", "DndOff"),
+        ("--dnd-toggle", "DndToggle"),
+    ];
+    if let Some((_, method)) = control_methods.iter().find(|(flag, _)| args.get(1).map(String::as_str) == Some(*flag)) {
+        if let Err(e) = bus::dbus::call_control_method(method) {
+            eprintln!("Couldn't reach wired: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let maybe_watcher = Config::init();
 
+    // `wired --print-config` shows the config as wired sees it, with defaults filled in and the
+    // layout blocks arranged into a tree.
+    if args.len() > 1 && args[1] == "--print-config" {
+        match ron::ser::to_string_pretty(Config::get(), ron::ser::PrettyConfig::new()) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("Couldn't print config: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
+    // `wired --render <out.png> <summary> [body] [reference.png]` draws a single notification to a
+    // PNG without touching X11 or dbus, optionally checking it against a reference image.
+    if args.len() > 1 && args[1] == "--render" {
+        if args.len() < 4 {
+            eprintln!("Usage: wired --render <out.png> <summary> [body] [reference.png]");
+            std::process::exit(2);
+        }
+
+        let body = args.get(4).map(String::as_str).unwrap_or("");
+        let reference = args.get(5).map(Path::new);
+        match rendering::offscreen::render(&args[3], body, Path::new(&args[2]), reference) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("Offscreen render failed: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+
     let mut event_loop = EventLoop::new_x11().expect("Couldn't create an X11 event loop.");
     let mut manager = NotifyWindowManager::new(&event_loop);
 
     // Allows us to receive messages from dbus.
+    // Notifications sent before we're ready aren't lost, so there's no need for a separate startup
+    // buffer: we only take the bus name once the manager exists, and our method handlers only run
+    // from `connection.incoming()` in the event loop.  A `Notify` sent the moment we take the name
+    // waits in the connection until the first tick, and then waits in the channel until the
+    // messages are handled just after (see the `queued_messages_wait_for_main_loop` test).
     let (connection, receiver) = bus::dbus::get_connection();
 
     let mut poll_interval = Duration::from_millis(Config::get().poll_interval);
+    let mut idle_poll_interval = Duration::from_millis(Config::get().idle_poll_interval);
     let mut prev_instant = Instant::now();
     event_loop.run_return(move |event, event_loop, control_flow| {
         match event {
@@ -47,11 +109,14 @@ fn main() {
                 let time_passed = now - prev_instant;
                 prev_instant = now;
                 manager.update(time_passed);
+                manager.release_staggered(time_passed, event_loop);
+                manager.promote_overflow(event_loop);
 
                 // Check dbus signals.
                 // If we don't do get incoming signals, notify sender will block when sending.
-                let signal = connection.incoming(0).next();
-                if let Some(message) = signal {
+                // Handle everything that's queued up, rather than one message per tick, so a burst
+                // of notifications (e.g. at startup) doesn't trickle in.
+                for message in connection.incoming(0) {
                     if message.msg_type() == MessageType::Signal &&
                        &*message.interface().unwrap() == "org.freedesktop.DBus" &&
                        &*message.member().unwrap() == "NameAcquired" &&
@@ -60,9 +125,18 @@ fn main() {
                     }
                 }
 
-                if let Ok(x) = receiver.try_recv() {
-                    //spawn_window(x, &mut manager, &event_loop);
-                    manager.new_notification(x, event_loop);
+                while let Ok(x) = receiver.try_recv() {
+                    match x {
+                        //spawn_window(x, &mut manager, &event_loop);
+                        Message::Notify(n) => manager.new_notification(n, event_loop),
+                        Message::CloseNotification(id) => manager.close_notification(id),
+                        Message::ReplayLastAction => manager.replay_last_action(),
+                        Message::ToggleCenter => manager.toggle_center(event_loop),
+                        Message::DismissLast => manager.dismiss_last(),
+                        Message::DismissAll => manager.dismiss_all(),
+                        Message::SetDnd(enabled) => manager.set_dnd(enabled, event_loop),
+                        Message::ToggleDnd => manager.toggle_dnd(event_loop),
+                    }
                 }
 
                 // If the watcher exists, then we should process watcher events.
@@ -78,6 +152,7 @@ fn main() {
                                     if file_name == "wired.ron" {
                                         Config::try_reload(p);
                                         poll_interval = Duration::from_millis(Config::get().poll_interval);
+                                        idle_poll_interval = Duration::from_millis(Config::get().idle_poll_interval);
                                     }
                                 }
                             },
@@ -86,8 +161,9 @@ fn main() {
                     }
                 }
 
-                // Restart timer for next loop.
-                *control_flow = ControlFlow::WaitUntil(now + poll_interval);
+                // Restart timer for next loop.  With nothing on screen, we only need to check dbus.
+                let interval = if manager.is_idle() { idle_poll_interval } else { poll_interval };
+                *control_flow = ControlFlow::WaitUntil(now + interval);
             },
 
             // Window becomes visible and then position is set.  Need fix.
@@ -97,6 +173,10 @@ fn main() {
             // TODO: fix this givinng whole window event.
             Event::WindowEvent { window_id, event, .. } => manager.process_event(window_id, event),
 
+            // Global key presses, for media keys.  winit gives us X11 keycodes minus 8.
+            Event::DeviceEvent { event: DeviceEvent::Key(input), .. } if input.state == ElementState::Pressed =>
+                manager.process_key(input.scancode + 8),
+
 
             // Poll continuously runs the event loop, even if the os hasn't dispatched any events.
             // This is ideal for games and similar applications.
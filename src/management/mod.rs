@@ -1,10 +1,14 @@
 use std::time::Duration;
+use std::io::Write;
 use std::process::{Command, Stdio};
-use std::collections::HashMap;
+use std::path::Path;
+use std::collections::{HashMap, VecDeque};
 
 use winit::{
     event_loop::EventLoopWindowTarget,
     window::WindowId,
+    platform::unix::WindowExtUnix,
+    monitor::MonitorHandle,
     event::ElementState,
     event::MouseButton,
     event::WindowEvent,
@@ -12,12 +16,16 @@ use winit::{
 };
 
 use crate::{
-    rendering::window::{NotifyWindow, UpdateModes},
-    rendering::layout::{LayoutElement, LayoutBlock},
+    rendering::window::{NotifyWindow, UpdateModes, GroupInfo},
+    rendering::layout::{LayoutElement, LayoutBlock, Hook},
+    rendering::blocks::notification_block::MonitorSelection,
     //notification::Notification,
-    bus::dbus::Notification,
-    maths_utility::Rect,
-    config::Config,
+    bus::dbus::{self, Notification, CloseReason, Sound, Urgency},
+    maths_utility::{Rect, Vec2},
+    config::{Config, AnchorPosition, AnchorOrigin, GroupBy, AnnounceConfig, EmptyNotifications, QueueOrder, Overflow},
+    x11_utility,
+    power_utility,
+    rules::{self, PinStacking, RuleOutput},
 };
 
 pub struct NotifyWindowManager {
@@ -25,8 +33,52 @@ pub struct NotifyWindowManager {
     pub base_window: winit::window::Window,
     pub monitor_windows: HashMap<u32, Vec<NotifyWindow>>,
     pub dirty: bool,
+
+    // Notifications whose windows have been closed, oldest first.
+    pub history: VecDeque<Notification>,
+    // Id of the notification most recently dismissed by the user.
+    last_dismissed: Option<u32>,
+
+    // Whether animations are paused because we're on battery, and time until we check again.
+    pub animations_paused: bool,
+    power_check_timer: Duration,
+
+    // Monitor layout the last time we checked, and time until we check again.
+    monitors: Vec<Rect>,
+    monitor_check_timer: Duration,
+
+    // Do not disturb, see `set_dnd()`.
+    dnd: bool,
+    // Whether the stack is on the opposite horizontal and/or vertical side of the monitor to where
+    // it's configured, see `Config::avoid_focused_window`.
+    stack_flip: (bool, bool),
+
+    // Notifications which are being held back from being shown, in the order they arrived.  They're
+    // all shown at once by `flush_queue()`.
+    queue: Vec<Notification>,
+    // Flushed notifications waiting for their turn to be shown, and time until the next one is, see
+    // `Config::flush_stagger_ms`.
+    releasing: VecDeque<Notification>,
+    release_timer: Duration,
+    // Notifications waiting for room on screen, oldest first, see `Config::overflow`.
+    overflow: VecDeque<Notification>,
+
+    // Windows of the notification center, newest first.  Empty when the center is closed.
+    center: Vec<NotifyWindow>,
+
+    // Whether `Config::media_keys` are grabbed, and the keycodes we grabbed.
+    media_keys_grabbed: bool,
+    grabbed_keys: Vec<x11::xlib::KeyCode>,
 }
 
+// Checking the power state means reading from sysfs, so don't do it every update.
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// winit doesn't tell us when monitors are added or removed, so we have to poll.
+const MONITOR_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// Id of the "+N more" notification shown while notifications are held back by `Config::overflow`.
+// Apps' notifications never get this id, so they can't close or replace it.
+const OVERFLOW_INDICATOR_ID: u32 = dbus::INTERNAL_ID;
+
 impl NotifyWindowManager {
     pub fn new(el: &EventLoopWindowTarget<()>) -> Self {
         let monitor_windows = HashMap::new();
@@ -40,25 +92,208 @@ impl NotifyWindowManager {
             base_window,
             monitor_windows,
             dirty: false,
+            history: VecDeque::new(),
+            last_dismissed: None,
+            animations_paused: false,
+            power_check_timer: Duration::default(),
+            monitors: vec![],
+            monitor_check_timer: Duration::default(),
+            dnd: false,
+            stack_flip: (false, false),
+            queue: vec![],
+            releasing: VecDeque::new(),
+            release_timer: Duration::default(),
+            overflow: VecDeque::new(),
+            center: vec![],
+            media_keys_grabbed: false,
+            grabbed_keys: vec![],
         }
     }
 
     // Summon a new notification.
-    pub fn new_notification(&mut self, notification: Notification, el: &EventLoopWindowTarget<()>) {
-        if let LayoutElement::NotificationBlock(p) = &Config::get().layout.as_ref().unwrap().params {
-            let window = NotifyWindow::new(el, notification, &self);
+    pub fn new_notification(&mut self, mut notification: Notification, el: &EventLoopWindowTarget<()>) {
+        if let LayoutElement::NotificationBlock(_) = &Config::get().layout.as_ref().unwrap().params {
+            let cfg = Config::get();
+            if self.dnd && !(cfg.dnd_allow_critical && notification.urgency == Urgency::Critical) {
+                self.hold_for_dnd(notification);
+                return;
+            }
+
+            let rule_output = rules::evaluate(&Config::get().rules, &notification);
+            if let Some(timeout) = rule_output.timeout {
+                notification.timeout = timeout;
+            }
+
+            if let Some(announce) = &Config::get().announce {
+                announce_notification(announce, &notification);
+            }
+
+            // Update the notification being replaced in place, rather than closing it and opening a
+            // new window, so that quickly updating notifications (e.g. download progress) don't
+            // flicker.  Notifications grouped behind another one (see `Config::group_by`) are
+            // updated where they are in the group.  If it's already gone, the replacement is shown
+            // as a new notification.
+            if notification.replaces_id != 0 {
+                let id = notification.replaces_id;
+                let replaced = self.monitor_windows.values_mut()
+                    .flat_map(|windows| windows.iter_mut())
+                    .find(|w| {
+                        !w.marked_for_destroy &&
+                            (w.notification.id == id || w.grouped.iter().any(|(n, _)| n.id == id))
+                    });
+
+                if let Some(window) = replaced {
+                    if window.notification.id == id {
+                        window.replace_notification(notification, rule_output);
+                        self.dirty = true;
+                    } else {
+                        window.replace_grouped(notification, rule_output);
+                    }
+                    return;
+                }
+            }
+
+            if rule_output.replace_duplicates.unwrap_or(false) {
+                let duplicate = self.monitor_windows.values_mut()
+                    .flat_map(|windows| windows.iter_mut())
+                    .find(|w| {
+                        !w.marked_for_destroy &&
+                            w.notification.app_name == notification.app_name &&
+                            w.notification.summary == notification.summary
+                    });
+
+                if let Some(window) = duplicate {
+                    // The old notification won't be seen again, but the app might be waiting to hear
+                    // that it's closed.
+                    dbus::signal_notification_closed(window.notification.id, CloseReason::Undefined);
+                    window.replace_notification(notification, rule_output);
+                    self.dirty = true;
+                    return;
+                }
+            }
+
+            // Show the notification in place of the last one from the same app, see `Config::group_by`.
+            if cfg.group_by == GroupBy::AppName {
+                let group = self.monitor_windows.values_mut()
+                    .flat_map(|windows| windows.iter_mut())
+                    .find(|w| {
+                        !w.marked_for_destroy &&
+                            w.notification.id != OVERFLOW_INDICATOR_ID &&
+                            w.notification.app_name == notification.app_name
+                    });
+
+                if let Some(window) = group {
+                    window.push_grouped(notification, rule_output);
+                    self.dirty = true;
+                    return;
+                }
+            }
+
+            // Show the notification on a card with others that just arrived from the same app, see
+            // `Config::combine_window_ms`.
+            if let Some(window) = self.find_card(&notification) {
+                window.combine(notification, rule_output);
+                self.dirty = true;
+                return;
+            }
+
+            // Hold the notification back if there isn't room for it, see `Config::overflow`.  Once
+            // anything's held back, everything after it is too, so they're shown in order.
+            if cfg.overflow == Overflow::Queue && cfg.max_notifications > 0 {
+                if let Some(queued) = self.overflow.iter_mut().find(|n| n.id == notification.replaces_id) {
+                    *queued = notification;
+                    return;
+                }
+
+                if !self.overflow.is_empty() || self.showing_count(self.stack_monitor()) >= cfg.max_notifications {
+                    self.overflow.push_back(notification);
+                    self.update_overflow_indicator(el);
+                    return;
+                }
+            }
+
+            self.spawn_window(notification, rule_output, el);
+        }
+    }
+
+    // Open a window for a notification that's been through `new_notification()`.
+    fn spawn_window(&mut self, notification: Notification, rule_output: RuleOutput, el: &EventLoopWindowTarget<()>) {
+        if Config::get().avoid_focused_window {
+            self.update_stack_flip();
+        }
+
+        if let LayoutElement::NotificationBlock(_) = &Config::get().layout.as_ref().unwrap().params {
+            // Make room for a notification pinned to the same spot, see `RuleOutput::pin_stacking`.
+            if let Some(pin) = &rule_output.pin_position {
+                if rule_output.pin_stacking.clone().unwrap_or_default() == PinStacking::Replace {
+                    let pinned = self.monitor_windows.values_mut()
+                        .flat_map(|windows| windows.iter_mut())
+                        .filter(|w| w.rule_output.pin_position.as_ref().map_or(false, |p| p.x == pin.x && p.y == pin.y));
+
+                    for window in pinned {
+                        window.close(CloseReason::Undefined);
+                    }
+                }
+            }
+
+            let mut window = NotifyWindow::new(el, notification, rule_output, &self);
+            let anchor_origin = window.rule_output.anchor_origin.as_ref().unwrap_or(&Config::get().anchor_origin);
+            if *anchor_origin == AnchorOrigin::FocusedWindow {
+                window.anchor_rect = self.base_window.xlib_display()
+                    .and_then(|display| unsafe { x11_utility::active_window_rect(display) })
+                    .map(|(x, y, width, height)| Rect::new(x as f64, y as f64, width as f64, height as f64));
+            }
+
+            // The window couldn't be set up for drawing, see `NotifyWindow::init()`.
+            if window.marked_for_destroy {
+                dbus::signal_notification_closed(window.notification.id, window.close_reason);
+                return;
+            }
+
+            if window.is_empty() {
+                match &Config::get().empty_notifications {
+                    EmptyNotifications::Show => {},
+                    EmptyNotifications::Suppress => {
+                        // The window was never shown, so it's fine to just drop it.
+                        dbus::signal_notification_closed(window.notification.id, CloseReason::Undefined);
+                        return;
+                    },
+                    EmptyNotifications::Fallback(text) => {
+                        window.notification.summary = text.clone();
+                        window.winit().set_title(&NotifyWindow::title(&window.notification));
+                        window.relayout();
+                    },
+                }
+            }
+
+            if Config::get().enable_sounds {
+                if let Some(sound) = &window.notification.sound {
+                    play_sound(sound);
+                }
+            }
 
             let windows = self.monitor_windows
-                .entry(p.monitor)
+                .entry(window.monitor)
                 .or_insert(vec![]);
 
-            // Push a new notification window.
-            windows.push(window);
+            // Push a new notification window.  When stacking by app, it goes after the last
+            // notification from the same app instead of at the end.
+            let same_app = windows.iter().rposition(|w| w.notification.app_name == window.notification.app_name);
+            match same_app {
+                Some(idx) if Config::get().stack_by_app => {
+                    // Draw attention to the group that the notification joined.
+                    for w in windows.iter_mut().filter(|w| w.notification.app_name == window.notification.app_name) {
+                        w.start_pulse();
+                    }
+                    windows.insert(idx + 1, window);
+                },
+                _ => windows.push(window),
+            }
 
             // If we've exceeded max notifications, then mark the top-most one for destroy.
             let cfg = Config::get();
-            if cfg.max_notifications > 0 && windows.len() > cfg.max_notifications {
-                windows.first_mut().unwrap().marked_for_destroy = true;
+            if cfg.overflow == Overflow::CloseOldest && cfg.max_notifications > 0 && windows.len() > cfg.max_notifications {
+                windows.first_mut().unwrap().close(CloseReason::Undefined);
             }
 
 
@@ -67,7 +302,263 @@ impl NotifyWindowManager {
         }
     }
 
+    // The window whose card `notification` should join, if any, see `Config::combine_window_ms`.
+    fn find_card(&mut self, notification: &Notification) -> Option<&mut NotifyWindow> {
+        let cfg = Config::get();
+        if cfg.combine_window_ms == 0 {
+            return None;
+        }
+
+        self.monitor_windows.values_mut()
+            .flat_map(|windows| windows.iter_mut())
+            .find(|w| {
+                !w.marked_for_destroy &&
+                    w.notification.id != OVERFLOW_INDICATOR_ID &&
+                    w.notification.app_name == notification.app_name &&
+                    w.combined.len() + 1 < cfg.max_combined &&
+                    (notification.time - w.newest_time()).num_milliseconds() <= cfg.combine_window_ms as i64
+            })
+    }
+
+    // Windows on a monitor which are, or will be, showing, not counting the "+N more" notification.
+    fn showing_count(&self, monitor: u32) -> usize {
+        self.monitor_windows.get(&monitor).map_or(0, |windows| {
+            windows.iter()
+                .filter(|w| !w.marked_for_destroy && w.notification.id != OVERFLOW_INDICATOR_ID)
+                .count()
+        })
+    }
+
+    // Show held back notifications as room is made for them, see `Config::overflow`.
+    pub fn promote_overflow(&mut self, el: &EventLoopWindowTarget<()>) {
+        let cfg = Config::get();
+        while self.showing_count(self.stack_monitor()) < cfg.max_notifications || cfg.max_notifications == 0 {
+            match self.overflow.pop_front() {
+                Some(notification) => {
+                    let rule_output = rules::evaluate(&cfg.rules, &notification);
+                    self.spawn_window(notification, rule_output, el);
+                },
+                None => break,
+            }
+        }
+
+        self.update_overflow_indicator(el);
+    }
+
+    // Move the stack away from the focused window, see `Config::avoid_focused_window`.  The stack
+    // moves to the other side of the monitor along each axis where the focused window's center is
+    // on the same side as the stack.
+    fn update_stack_flip(&mut self) {
+        let focused = self.base_window.xlib_display()
+            .and_then(|display| unsafe { x11_utility::active_window_rect(display) });
+        let (x, y, width, height) = match focused {
+            Some(rect) => rect,
+            None => {
+                self.stack_flip = (false, false);
+                return;
+            },
+        };
+
+        let monitor = self.base_window.available_monitors()
+            .nth(self.stack_monitor() as usize)
+            .unwrap_or(self.base_window.primary_monitor());
+        let (pos, size) = (monitor.position(), monitor.size());
+        let monitor_rect = Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into());
+        let center_x = monitor_rect.x() + monitor_rect.width() / 2.0;
+        let center_y = monitor_rect.y() + monitor_rect.height() / 2.0;
+        let focused_x = x as f64 + width as f64 / 2.0;
+        let focused_y = y as f64 + height as f64 / 2.0;
+
+        let anchor = &Config::get().layout.as_ref().unwrap().hook.parent_anchor;
+        let flip_x = match anchor {
+            AnchorPosition::TL | AnchorPosition::ML | AnchorPosition::BL => focused_x < center_x,
+            AnchorPosition::TR | AnchorPosition::MR | AnchorPosition::BR => focused_x > center_x,
+            AnchorPosition::MT | AnchorPosition::MB => false,
+        };
+        let flip_y = match anchor {
+            AnchorPosition::TL | AnchorPosition::MT | AnchorPosition::TR => focused_y < center_y,
+            AnchorPosition::BL | AnchorPosition::MB | AnchorPosition::BR => focused_y > center_y,
+            AnchorPosition::ML | AnchorPosition::MR => false,
+        };
+
+        if self.stack_flip != (flip_x, flip_y) {
+            self.stack_flip = (flip_x, flip_y);
+            self.dirty = true;
+        }
+    }
+
+    // Index of the monitor that new notifications go on, see `MonitorSelection`.
+    pub fn stack_monitor(&self) -> u32 {
+        let selection = match &Config::get().layout.as_ref().unwrap().params {
+            LayoutElement::NotificationBlock(p) => &p.monitor,
+            _ => return 0,
+        };
+
+        let monitors: Vec<MonitorHandle> = self.base_window.available_monitors().collect();
+        let primary_idx = self.primary_monitor();
+
+        match selection {
+            MonitorSelection::Index(idx) => *idx,
+            MonitorSelection::Primary => primary_idx,
+            MonitorSelection::Cursor => {
+                let pointer = self.base_window.xlib_display()
+                    .and_then(|display| unsafe { x11_utility::pointer_position(display) });
+                let (x, y) = match pointer {
+                    Some(pos) => pos,
+                    None => return primary_idx,
+                };
+
+                monitors.iter()
+                    .position(|m| {
+                        let (pos, size) = (m.position(), m.size());
+                        let rect = Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into());
+                        let (x, y) = (x as f64, y as f64);
+                        x >= rect.left() && x < rect.right() && y >= rect.top() && y < rect.bottom()
+                    })
+                    .map_or(primary_idx, |idx| idx as u32)
+            },
+        }
+    }
+
+    // Index of the primary monitor.
+    fn primary_monitor(&self) -> u32 {
+        let primary = self.base_window.primary_monitor();
+        self.base_window.available_monitors()
+            .position(|m| m == primary)
+            .unwrap_or(0) as u32
+    }
+
+    // Keep the "+N more" notification up to date and at the end of the stack, or close it once
+    // nothing's held back.  It's a normal notification window, so it's drawn with the layout.
+    fn update_overflow_indicator(&mut self, el: &EventLoopWindowTarget<()>) {
+        let summary = format!("+{} more", self.overflow.len());
+        let found = self.monitor_windows.iter()
+            .find_map(|(monitor, windows)| {
+                windows.iter()
+                    .position(|w| w.notification.id == OVERFLOW_INDICATOR_ID && !w.marked_for_destroy)
+                    .map(|idx| (*monitor, idx))
+            });
+        let (monitor, indicator) = match found {
+            Some((monitor, idx)) => (monitor, Some(idx)),
+            None => (self.stack_monitor(), None),
+        };
+
+        if self.overflow.is_empty() {
+            if let Some(idx) = indicator {
+                self.monitor_windows.get_mut(&monitor).unwrap()[idx].close(CloseReason::Undefined);
+                self.dirty = true;
+            }
+            return;
+        }
+
+        // A timeout of 0 means it never expires.  `NotifyWindow` doesn't apply
+        // `Config::max_timeout` or `Config::max_lifetime` to it.
+        let mut notification = match indicator {
+            Some(idx) if self.monitor_windows[&monitor][idx].notification.summary == summary => None,
+            _ => Some(Notification::from_dbus(
+                OVERFLOW_INDICATOR_ID, "wired", 0, "", &summary, "", vec![], HashMap::new(), 0,
+            )),
+        };
+
+        let new_window = match indicator {
+            Some(_) => None,
+            None => Some(NotifyWindow::new(el, notification.take().unwrap(), RuleOutput::default(), &self)),
+        };
+
+        let windows = self.monitor_windows.entry(monitor).or_insert(vec![]);
+        let window = match (indicator, new_window) {
+            (Some(idx), _) => {
+                if idx + 1 == windows.len() && notification.is_none() {
+                    return;
+                }
+
+                let mut window = windows.remove(idx);
+                if let Some(notification) = notification {
+                    window.replace_notification(notification, RuleOutput::default());
+                }
+                window
+            },
+            (None, Some(window)) => window,
+            (None, None) => return,
+        };
+
+        windows.push(window);
+        self.dirty = true;
+    }
+
+    // Turn do not disturb on or off, letting anyone listening on dbus know.  While it's on,
+    // notifications are held back (see `Config::dnd_queue`), and they're shown when it's turned off.
+    pub fn set_dnd(&mut self, enabled: bool, el: &EventLoopWindowTarget<()>) {
+        if self.dnd != enabled {
+            self.dnd = enabled;
+            dbus::signal_dnd_changed(enabled);
+
+            if !enabled {
+                self.flush_queue(el);
+            }
+        }
+    }
+
+    pub fn toggle_dnd(&mut self, el: &EventLoopWindowTarget<()>) {
+        self.set_dnd(!self.dnd, el);
+    }
+
+    // Keep a notification that arrived during do not disturb for later, or just in the history, see
+    // `Config::dnd_queue`.  Updates to held back notifications replace them.
+    fn hold_for_dnd(&mut self, notification: Notification) {
+        if !Config::get().dnd_queue {
+            dbus::signal_notification_closed(notification.id, CloseReason::Undefined);
+            self.history.retain(|n| n.id != notification.id);
+            self.history.push_back(notification);
+            return;
+        }
+
+        match self.queue.iter_mut().find(|n| n.id == notification.replaces_id) {
+            Some(queued) => *queued = notification,
+            None => self.queue.push(notification),
+        }
+    }
+
+    // Show every held back notification, in the order given by `Config::queue_order`.
+    pub fn flush_queue(&mut self, el: &EventLoopWindowTarget<()>) {
+        let mut queued: Vec<Notification> = self.queue.drain(..).collect();
+        order_queue(&mut queued, &Config::get().queue_order);
+
+        if Config::get().flush_stagger_ms == 0 {
+            for notification in queued {
+                self.new_notification(notification, el);
+            }
+            return;
+        }
+
+        // The first one can be shown straight away.
+        if self.releasing.is_empty() {
+            self.release_timer = Duration::default();
+        }
+        self.releasing.extend(queued);
+        self.release_staggered(Duration::default(), el);
+    }
+
+    // Show the next flushed notification whenever `Config::flush_stagger_ms` has passed.
+    pub fn release_staggered(&mut self, delta_time: Duration, el: &EventLoopWindowTarget<()>) {
+        if self.releasing.is_empty() {
+            return;
+        }
+
+        self.release_timer = self.release_timer.checked_sub(delta_time).unwrap_or_default();
+        while self.release_timer == Duration::default() {
+            match self.releasing.pop_front() {
+                Some(notification) => self.new_notification(notification, el),
+                None => break,
+            }
+            self.release_timer = Duration::from_millis(Config::get().flush_stagger_ms);
+        }
+    }
+
     pub fn update(&mut self, delta_time: Duration) {
+        self.update_power_state(delta_time);
+        self.check_monitors(delta_time);
+
         // Returning dirty from a window update means the window has been deleted / needs
         // positioning updated.
         for (_monitor, windows) in &mut self.monitor_windows {
@@ -79,17 +570,361 @@ impl NotifyWindowManager {
         if self.dirty {
             self.update_positions();
             // Finally drop windows.
-            for (_monitor_id, windows) in &mut self.monitor_windows {
-                windows.retain(|w| !w.marked_for_destroy);
+            self.remove_destroyed_windows();
+        }
+
+        self.update_media_keys();
+
+        let mut center_dirty = false;
+        for window in &mut self.center {
+            center_dirty |= window.update(delta_time);
+        }
+        if center_dirty {
+            self.update_center_positions();
+        }
+    }
+
+    // Newest notification on screen which has the given action.
+    fn find_action_target(&self, action: &str) -> Option<u32> {
+        self.monitor_windows
+            .values()
+            .flatten()
+            .filter(|w| !w.marked_for_destroy && w.notification.actions.iter().any(|(key, _)| key == action))
+            .map(|w| w.notification.id)
+            .max()
+    }
+
+    // Grab media keys while there's a notification which can use them, and release them otherwise.
+    fn update_media_keys(&mut self) {
+        let media_keys = &Config::get().media_keys;
+        let wanted = media_keys.iter().any(|(_, action)| self.find_action_target(action).is_some());
+        if wanted == self.media_keys_grabbed {
+            return;
+        }
+
+        self.media_keys_grabbed = wanted;
+        let display = self.base_window.xlib_display().expect("Couldn't get xlib display.");
+        unsafe {
+            for keycode in self.grabbed_keys.drain(..) {
+                x11_utility::ungrab_key(display, keycode);
+            }
+
+            if wanted {
+                for (key, _) in media_keys {
+                    match x11_utility::keycode_for(display, key) {
+                        Some(keycode) => {
+                            x11_utility::grab_key(display, keycode);
+                            self.grabbed_keys.push(keycode);
+                        },
+                        None => println!("Couldn't find a key called \"{}\" to grab.", key),
+                    }
+                }
+            }
+        }
+    }
+
+    // Handle a global key press.  Only media keys are grabbed, so anything else is ignored.
+    pub fn process_key(&self, keycode: u32) {
+        if !self.media_keys_grabbed {
+            return;
+        }
+
+        let display = self.base_window.xlib_display().expect("Couldn't get xlib display.");
+        for (key, action) in &Config::get().media_keys {
+            let matches = unsafe { x11_utility::keycode_for(display, key) }
+                .map_or(false, |k| k as u32 == keycode);
+
+            if matches {
+                if let Some(id) = self.find_action_target(action) {
+                    dbus::signal_action_invoked(id, action);
+                }
+            }
+        }
+    }
+
+    // Whether there's nothing on screen to update, so we can tick less often.
+    pub fn is_idle(&self) -> bool {
+        self.center.is_empty() &&
+            self.releasing.is_empty() &&
+            self.monitor_windows.values().all(|windows| windows.is_empty())
+    }
+
+    // Open the notification center, showing every notification we still know about, or close it if
+    // it's already open.
+    pub fn toggle_center(&mut self, el: &EventLoopWindowTarget<()>) {
+        if !self.center.is_empty() {
+            // Dropping the windows closes them.
+            self.center.clear();
+            return;
+        }
+
+        let cfg = Config::get();
+        // Notifications which are still showing are newer than anything in the history.
+        let mut live: Vec<Notification> = self.monitor_windows
+            .values()
+            .flatten()
+            .filter(|w| !w.marked_for_destroy && w.notification.id != OVERFLOW_INDICATOR_ID)
+            .map(|w| w.notification.clone())
+            .collect();
+        live.sort_by(|a, b| b.id.cmp(&a.id));
+
+        let entries: Vec<Notification> = live
+            .into_iter()
+            .chain(self.history.iter().rev().cloned())
+            .take(cfg.center.max_entries)
+            .collect();
+
+        for notification in entries {
+            let rule_output = rules::evaluate(&cfg.rules, &notification);
+            let mut window = NotifyWindow::new(el, notification, rule_output, &self);
+            // The window couldn't be set up for drawing, see `NotifyWindow::init()`.  The
+            // notification itself is still open, or was already closed, so there's nothing to tell
+            // the app.
+            if window.marked_for_destroy {
+                continue;
+            }
+
+            // Entries stay until they're closed, or the center is.
+            window.update_mode.remove(UpdateModes::FUSE);
+            window.ignore_max_lifetime = true;
+            self.center.push(window);
+        }
+
+        if self.center.is_empty() {
+            println!("No notifications to show in the notification center.");
+        }
+
+        self.update_center_positions();
+    }
+
+    // The center is a single column in a corner of the primary monitor.
+    fn update_center_positions(&mut self) {
+        let cfg = Config::get();
+        let monitor = self.base_window.primary_monitor();
+        let (pos, size) = (monitor.position(), monitor.size());
+        let monitor_rect = Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into());
+        let monitor_rect = cfg.screen_margin.shrink(&monitor_rect);
+
+        let corner = Hook {
+            parent_anchor: cfg.center.corner.clone(),
+            self_anchor: cfg.center.corner.clone(),
+            vertical_align: None,
+        };
+        // The list grows away from the edge of the monitor it's anchored to.
+        let direction = match cfg.center.corner {
+            AnchorPosition::BL | AnchorPosition::MB | AnchorPosition::BR => -1.0,
+            _ => 1.0,
+        };
+
+        let mut distance = 0.0;
+        for window in &mut self.center {
+            let window_rect = window.get_inner_rect();
+            let mut pos = LayoutBlock::find_anchor_pos(&corner, &Vec2::default(), &monitor_rect, &window_rect);
+            pos.y += direction * distance;
+            distance += window_rect.height() + cfg.center.gap;
+
+            // There's no scrolling yet, so entries which don't fit on the monitor aren't shown.
+            let fits = distance - cfg.center.gap <= monitor_rect.height();
+            window.set_position(pos.x, pos.y);
+            window.set_visible(fits);
+        }
+    }
+
+    // Remove an entry from the notification center, closing the notification for good.
+    fn close_center_entry(&mut self, idx: usize) {
+        let window = self.center.remove(idx);
+        let id = window.notification.id;
+
+        // If the notification is still showing, it's dismissed there too.  Otherwise it was already
+        // closed, and we just forget about it.
+        let live = self.monitor_windows
+            .values_mut()
+            .flatten()
+            .find(|w| w.notification.id == id && !w.marked_for_destroy);
+        match live {
+            Some(w) => {
+                w.close(CloseReason::Dismissed);
+                self.last_dismissed = Some(id);
+                self.dirty = true;
+            },
+            None => self.history.retain(|n| n.id != id),
+        }
+
+        self.update_center_positions();
+    }
+
+    fn process_center_event(&mut self, idx: usize, event: event::WindowEvent) {
+        let button = match event {
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => 1,
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Right, .. } => 2,
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Middle, .. } => 3,
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Other(u), .. } => u,
+            _ => return,
+        };
+
+        let shortcuts = &Config::get().shortcuts;
+        if button == shortcuts.notification_close {
+            self.close_center_entry(idx);
+        } else if button == shortcuts.notification_closeall {
+            self.center.clear();
+        } else if button == shortcuts.notification_url {
+            find_and_open_url(self.center[idx].notification.body.clone());
+        }
+    }
+
+    // Drop windows which are marked for destroy, keeping their notifications in the history.
+    fn remove_destroyed_windows(&mut self) {
+        let history_length = Config::get().history_length;
+        for (_monitor_id, windows) in &mut self.monitor_windows {
+            let (destroyed, alive): (Vec<NotifyWindow>, Vec<NotifyWindow>) =
+                windows.drain(..).partition(|w| w.marked_for_destroy);
+            *windows = alive;
+
+            for window in destroyed {
+                // The "+N more" notification is ours, so there's nobody to tell.
+                if window.notification.id == OVERFLOW_INDICATOR_ID {
+                    continue;
+                }
+
+                // Closing a card or group closes everything in it.
+                dbus::signal_notification_closed(window.notification.id, window.close_reason);
+                for (notification, _) in window.grouped {
+                    dbus::signal_notification_closed(notification.id, window.close_reason);
+                    self.history.push_back(notification);
+                }
+                for entry in window.combined {
+                    dbus::signal_notification_closed(entry.notification.id, window.close_reason);
+                    self.history.push_back(entry.notification);
+                }
+                self.history.push_back(window.notification);
+            }
+        }
+
+        while self.history.len() > history_length {
+            self.history.pop_front();
+        }
+    }
+
+    // Invoke the default action of the most recently dismissed notification, in case it was
+    // dismissed by accident.
+    pub fn replay_last_action(&self) {
+        let notification = self.last_dismissed
+            .and_then(|id| self.history.iter().rev().find(|n| n.id == id));
+
+        match notification {
+            Some(n) if n.get_default_action().is_some() => dbus::signal_action_invoked(n.id, "default"),
+            Some(n) => println!("Last dismissed notification from \"{}\" has no default action to replay.", n.app_name),
+            None => println!("No dismissed notification to replay."),
+        }
+    }
+
+    // When monitors are added or removed, notifications on a monitor which has gone, or whose index
+    // now belongs to a different monitor, move to the primary monitor (see `migrate_windows()`).
+    fn check_monitors(&mut self, delta_time: Duration) {
+        if self.monitor_check_timer > delta_time {
+            self.monitor_check_timer -= delta_time;
+            return;
+        }
+
+        self.monitor_check_timer = MONITOR_CHECK_INTERVAL;
+        let monitors: Vec<Rect> = self.base_window
+            .available_monitors()
+            .map(|m| {
+                let (pos, size) = (m.position(), m.size());
+                Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into())
+            })
+            .collect();
+
+        if monitors == self.monitors {
+            return;
+        }
+
+        // The first check just finds out what's there.
+        if !self.monitors.is_empty() {
+            let primary = self.primary_monitor();
+            migrate_windows(&mut self.monitor_windows, &self.monitors, &monitors, primary);
+        }
+
+        for (monitor, windows) in &mut self.monitor_windows {
+            let monitor_rect = match monitors.get(*monitor as usize) {
+                Some(rect) => rect,
+                None => continue,
+            };
+
+            for window in windows.iter_mut().filter(|w| w.monitor != *monitor || w.monitor_rect != *monitor_rect) {
+                window.monitor = *monitor;
+                // Sizes can be a percentage of the monitor.
+                window.monitor_rect = monitor_rect.clone();
+                window.relayout();
+            }
+        }
+
+        self.monitors = monitors;
+        self.dirty = true;
+    }
+
+    fn update_power_state(&mut self, delta_time: Duration) {
+        let paused = if !Config::get().pause_animations_on_battery {
+            false
+        } else if self.power_check_timer > delta_time {
+            self.power_check_timer -= delta_time;
+            return;
+        } else {
+            self.power_check_timer = POWER_CHECK_INTERVAL;
+            power_utility::on_battery()
+        };
+
+        // Windows are only told when the power state changes; new windows pick it up when created.
+        if paused != self.animations_paused {
+            self.animations_paused = paused;
+            for window in self.monitor_windows.values_mut().flatten().chain(self.center.iter_mut()) {
+                window.set_animations_paused(paused);
+            }
+        }
+    }
+
+    // Work out which app group each window belongs to.  Windows relayout themselves if their group
+    // changed, so this needs to happen before positioning.
+    fn update_groups(&mut self) {
+        let stack_by_app = Config::get().stack_by_app;
+        for (_monitor, windows) in &mut self.monitor_windows {
+            let live: Vec<usize> = (0..windows.len()).filter(|&i| !windows[i].marked_for_destroy).collect();
+
+            let mut start = 0;
+            while start < live.len() {
+                let app_name = windows[live[start]].notification.app_name.clone();
+                let mut end = start + 1;
+                while stack_by_app && end < live.len() && windows[live[end]].notification.app_name == app_name {
+                    end += 1;
+                }
+
+                for (n, &idx) in live[start..end].iter().enumerate() {
+                    windows[idx].set_group(GroupInfo { first: n == 0, count: end - start });
+                }
+
+                start = end;
             }
         }
     }
 
     fn update_positions(&mut self) {
+        self.update_groups();
+
         let cfg = Config::get();
         // TODO: gotta do something about this... can't I just cast it?
         if let LayoutElement::NotificationBlock(p) = &cfg.layout.as_ref().unwrap().params {
-            for (monitor_id, windows) in &self.monitor_windows {
+            // The stack may have moved to the other side of the monitor, see
+            // `Config::avoid_focused_window`.
+            let root = cfg.layout.as_ref().unwrap();
+            let (flip_x, flip_y) = self.stack_flip;
+            let root_hook = root.hook.flipped(flip_x, flip_y);
+            let notification_hook = p.notification_hook.flipped(flip_x, flip_y);
+            let gap = Vec2::new(
+                if flip_x { -p.gap.x } else { p.gap.x },
+                if flip_y { -p.gap.y } else { p.gap.y },
+            );
+
+            for (monitor_id, windows) in &mut self.monitor_windows {
                 // If there are no windows for this monitor, leave it alone.
                 if windows.len() == 0 {
                     continue;
@@ -98,7 +933,7 @@ impl NotifyWindowManager {
                 // Grab a winit window reference to use to call winit functions.
                 // The functions here are just convenience functions to avoid needing a reference
                 // to the event loop -- they don't relate to the individual window.
-                let winit_utility = &windows[0].winit;
+                let winit_utility = windows[0].winit();
                 let monitor = winit_utility
                     .available_monitors()
                     .nth(*monitor_id as usize)
@@ -106,9 +941,29 @@ impl NotifyWindowManager {
 
                 let (pos, size) = (monitor.position(), monitor.size());
                 let monitor_rect = Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into());
-                let mut prev_rect = monitor_rect;
+                // Notifications are kept inside the monitor's margins.
+                let monitor_rect = cfg.screen_margin.shrink(&monitor_rect);
+                let mut prev_rect = monitor_rect.clone();
+                // The root block's offset can be a percentage of the monitor.
+                let offset = root.offset.resolve(&monitor_rect);
+                let root_offset = Vec2::new(
+                    if flip_x { -offset.x } else { offset.x },
+                    if flip_y { -offset.y } else { offset.y },
+                );
+                // Rects of windows placed using position hints so far, so that we can avoid
+                // stacking them perfectly on top of each other.
+                let mut hinted_rects: Vec<Rect> = vec![];
+
+                // Stacks anchored to the top or bottom middle of the monitor keep every notification
+                // centered, rather than lining them up with the previous one, which would look
+                // ragged when widths differ.
+                let centered = match root_hook.parent_anchor {
+                    AnchorPosition::MT | AnchorPosition::MB => true,
+                    _ => false,
+                };
 
                 let mut real_idx = 0;
+                let mut minimized_idx = 0;
                 for i in 0..windows.len() {
                     // Windows which are marked for destroy should be overlapped so that destroying them
                     // will be less noticeable.
@@ -116,26 +971,88 @@ impl NotifyWindowManager {
                         continue;
                     }
 
-                    let window = &windows[i];
+                    // Minimized windows sit in a row in their own corner, outside of the stack.
+                    if windows[i].minimized {
+                        let minimize = &cfg.minimize;
+                        let corner = Hook {
+                            parent_anchor: minimize.corner.clone(),
+                            self_anchor: minimize.corner.clone(),
+                            vertical_align: None,
+                        };
+
+                        let window_rect = windows[i].get_inner_rect();
+                        let mut pos = LayoutBlock::find_anchor_pos(&corner, &Vec2::default(), &monitor_rect, &window_rect);
+                        // Rows grow away from the edge of the monitor they're anchored to.
+                        let direction = match minimize.corner {
+                            AnchorPosition::TR | AnchorPosition::MR | AnchorPosition::BR => -1.0,
+                            _ => 1.0,
+                        };
+                        pos.x += direction * minimized_idx as f64 * (minimize.size + minimize.gap);
+
+                        windows[i].set_position(pos.x, pos.y);
+                        windows[i].set_visible(true);
+                        minimized_idx += 1;
+                        continue;
+                    }
+
+                    let window = &mut windows[i];
                     let mut window_rect = window.get_inner_rect();
 
+                    // Windows which ask for a specific position, or are pinned to one by a rule or
+                    // to the focused window, are placed there, outside of the stack.
+                    let fixed_pos = window.rule_output.pin_position.clone()
+                        .or_else(|| window.notification.get_position_hint())
+                        .or_else(|| window.anchor_rect.as_ref().map(|anchor_rect| {
+                            let offset = root.offset.resolve(anchor_rect);
+                            LayoutBlock::find_anchor_pos(&root.hook, &offset, anchor_rect, &window_rect)
+                        }));
+                    if let Some(hint_pos) = fixed_pos {
+                        window_rect.set_xy(hint_pos.x, hint_pos.y);
+
+                        // Cascade away from any other hinted window in the same spot.
+                        // Bounded, in case the cascade offset is zero.
+                        let cascade = &cfg.position_hint_cascade_offset;
+                        for _ in 0..hinted_rects.len() {
+                            let overlapping = hinted_rects.iter().any(|r| {
+                                (r.x() - window_rect.x()).abs() < 1.0 && (r.y() - window_rect.y()).abs() < 1.0
+                            });
+
+                            if !overlapping {
+                                break;
+                            }
+
+                            window_rect.set_xy(window_rect.x() + cascade.x, window_rect.y() + cascade.y);
+                        }
+
+                        window_rect.clamp_within(&monitor_rect);
+                        window.set_position(window_rect.x(), window_rect.y());
+                        window.set_visible(true);
+                        hinted_rects.push(window_rect);
+                        continue;
+                    }
+
                     // For the first notification, we attach to the monitor.
                     // For the second and more notifications, we attach to the previous
                     // notification.
                     let pos = if real_idx == 0 {
-                        LayoutBlock::find_anchor_pos(
-                            &cfg.layout.as_ref().unwrap().hook,
-                            &cfg.layout.as_ref().unwrap().offset,
-                            &prev_rect,
-                            &window_rect,
-                        )
+                        let pos = LayoutBlock::find_anchor_pos(&root_hook, &root_offset, &prev_rect, &window_rect);
+                        if cfg.avoid_focused_window {
+                            // A flipped offset could push the first notification off the monitor.
+                            let mut rect = window_rect.clone();
+                            rect.set_xy(pos.x, pos.y);
+                            rect.clamp_within(&monitor_rect);
+                            Vec2::new(rect.x(), rect.y())
+                        } else {
+                            pos
+                        }
+                    } else {
+                        LayoutBlock::find_anchor_pos(&notification_hook, &gap, &prev_rect, &window_rect)
+                    };
+                    let pos = if centered {
+                        let x = monitor_rect.x() + (monitor_rect.width() - window_rect.width()) / 2.0;
+                        Vec2::new(x + root_offset.x, pos.y)
                     } else {
-                        LayoutBlock::find_anchor_pos(
-                            &p.notification_hook,
-                            &p.gap,
-                            &prev_rect,
-                            &window_rect,
-                        )
+                        pos
                     };
 
                     // Note: `set_position` doesn't happen instantly.  If we read
@@ -161,6 +1078,66 @@ impl NotifyWindowManager {
     }
 
     pub fn process_event(&mut self, window_id: WindowId, event: event::WindowEvent) {
+        // The notification center has its own, simpler, set of shortcuts.
+        if let Some(idx) = self.center.iter().position(|w| w.winit().id() == window_id) {
+            self.process_center_event(idx, event);
+            return;
+        }
+
+        // Hovering a notification expands any text which is only showing a preview, and pauses it
+        // (see `Config::pause_on_hover`).  The window changes size, so the stack needs
+        // repositioning.
+        let hovered = match event {
+            WindowEvent::CursorEntered { .. } => Some(true),
+            WindowEvent::CursorLeft { .. } => Some(false),
+            _ => None,
+        };
+        if let Some(expanded) = hovered {
+            if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                let window = self.monitor_windows
+                    .get_mut(&monitor).unwrap()
+                    .get_mut(idx).unwrap();
+
+                if expanded {
+                    window.set_read();
+                }
+                window.set_hovered(expanded);
+                window.set_expanded(expanded);
+                self.dirty = true;
+            }
+        }
+
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                    let window = self.monitor_windows
+                        .get_mut(&monitor).unwrap()
+                        .get_mut(idx).unwrap();
+
+                    // Redraw when the cursor moves on or off a link, to change its highlight.
+                    let pos = Vec2::new(position.x, position.y);
+                    let old_link = window.cursor_pos.as_ref().and_then(|p| window.text.link_at(p));
+                    if window.text.link_at(&pos) != old_link {
+                        window.winit().request_redraw();
+                    }
+                    window.cursor_pos = Some(pos);
+                }
+            },
+            WindowEvent::CursorLeft { .. } => {
+                if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                    let window = self.monitor_windows
+                        .get_mut(&monitor).unwrap()
+                        .get_mut(idx).unwrap();
+
+                    if window.cursor_pos.as_ref().and_then(|p| window.text.link_at(p)).is_some() {
+                        window.winit().request_redraw();
+                    }
+                    window.cursor_pos = None;
+                }
+            },
+            _ => {},
+        }
+
         // Simplify button presses into a uint, which matches our config.
         let pressed = match event {
             WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
@@ -177,7 +1154,65 @@ impl NotifyWindowManager {
         // Match button press to config.
         let config = Config::get();
         if let Some(button) = pressed {
-            if button == config.shortcuts.notification_close {
+            // Any click restores a minimized window.
+            if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                let window = self.monitor_windows
+                    .get_mut(&monitor).unwrap()
+                    .get_mut(idx).unwrap();
+
+                if window.minimized {
+                    window.set_minimized(false);
+                    self.dirty = true;
+                    return;
+                }
+
+                // Left clicking an action button invokes that action, which is the end of the
+                // notification.  Left clicking anywhere else does whatever the left button is bound
+                // to.
+                if button == 1 {
+                    if let Some(action) = window.action_under_cursor() {
+                        dbus::signal_action_invoked(window.notification.id, &action);
+                        self.drop_window(window_id);
+                        return;
+                    }
+                }
+            }
+
+            if button == config.shortcuts.notification_minimize {
+                if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                    let window = self.monitor_windows
+                        .get_mut(&monitor).unwrap()
+                        .get_mut(idx).unwrap();
+
+                    window.set_minimized(true);
+                    self.dirty = true;
+                }
+
+            } else if button == config.shortcuts.notification_copy {
+                if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                    let window = self.monitor_windows
+                        .get_mut(&monitor).unwrap()
+                        .get_mut(idx).unwrap();
+
+                    if copy_to_clipboard(&window.notification) {
+                        window.show_copy_feedback();
+                    }
+                }
+
+            } else if button == config.shortcuts.notification_close {
+                // Clicking a notification away invokes its default action, if it has one.  Other
+                // bindings (like pause or copy) leave the app out of it.
+                if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+                    let notification = &self.monitor_windows.get(&monitor).unwrap().get(idx).unwrap().notification;
+                    if notification.get_default_action().is_some() {
+                        dbus::signal_action_invoked(notification.id, "default");
+                    }
+                }
+
+                if config.raise_source_on_click {
+                    self.raise_source_window(window_id);
+                }
+
                 self.drop_window(window_id);
 
             } else if button == config.shortcuts.notification_closeall {
@@ -187,7 +1222,10 @@ impl NotifyWindowManager {
                 let mut body = String::from("");
                 if let Some((monitor, idx)) = self.find_window_idx(window_id) {
                     let window = self.monitor_windows.get(&monitor).unwrap().get(idx).unwrap();
-                    body = window.notification.body.clone();
+                    // Prefer the link under the cursor, over the first one in the body.
+                    body = window.cursor_pos.as_ref()
+                        .and_then(|p| window.text.link_at(p))
+                        .unwrap_or_else(|| window.notification.body.clone());
                 }
 
                 find_and_open_url(body);
@@ -198,8 +1236,7 @@ impl NotifyWindowManager {
                         .get_mut(&monitor).unwrap()
                         .get_mut(idx).unwrap();
 
-                    window.update_mode.toggle(UpdateModes::FUSE);
-                    //window.update_enabled = !window.update_enabled;
+                    window.toggle_pause();
                 }
             }
         }
@@ -208,7 +1245,7 @@ impl NotifyWindowManager {
     // Find window across all monitors based on an id, which we receive from winit events.
     pub fn find_window_idx(&self, window_id: WindowId) -> Option<(u32, usize)> {
         for (monitor, windows) in &self.monitor_windows {
-            let found = windows.iter().position(|w| w.winit.id() == window_id);
+            let found = windows.iter().position(|w| w.winit().id() == window_id);
             if let Some(idx) = found {
                 return Some((*monitor, idx))
             }
@@ -217,22 +1254,124 @@ impl NotifyWindowManager {
         None
     }
 
+    // Raise the window of the application which sent a notification, if we can find it.
+    pub fn raise_source_window(&self, window_id: WindowId) {
+        if let Some((monitor, idx)) = self.find_window_idx(window_id) {
+            let window = self.monitor_windows.get(&monitor).unwrap().get(idx).unwrap();
+            let notification = &window.notification;
+            let display = window.winit().xlib_display().expect("Couldn't get xlib display.");
+
+            let source = unsafe {
+                notification.get_hint_i64("window-id")
+                    .map(|id| id as x11::xlib::Window)
+                    .or_else(|| {
+                        notification.get_hint_i64("sender-pid")
+                            .and_then(|pid| x11_utility::find_window_by_pid(display, pid as u32))
+                    })
+            };
+
+            match source {
+                Some(source) => unsafe { x11_utility::activate_window(display, source) },
+                None => println!("Couldn't find a window to raise for notification from \"{}\".", notification.app_name),
+            }
+        }
+    }
+
     pub fn drop_window(&mut self, window_id: WindowId) {
         if let Some((monitor, idx)) = self.find_window_idx(window_id) {
             let window = self.monitor_windows
                 .get_mut(&monitor).unwrap()
                 .get_mut(idx).unwrap();
 
-            window.marked_for_destroy = true;
+            // The "+N more" notification has no actions to replay.
+            if window.notification.id != OVERFLOW_INDICATOR_ID {
+                self.last_dismissed = Some(window.notification.id);
+            }
+            close_or_ungroup(window, CloseReason::Dismissed, &mut self.history);
+            self.dirty = true;
+        }
+    }
+
+    // Close a notification because the app that sent it asked us to.  Ids we don't know about
+    // (e.g. notifications which have already closed) are ignored, as the spec says.
+    pub fn close_notification(&mut self, id: u32) {
+        // The "+N more" notification is ours, and goes away by itself once there's room.
+        if id == OVERFLOW_INDICATOR_ID {
+            return;
+        }
+
+        let window = self.monitor_windows.values_mut()
+            .flat_map(|windows| windows.iter_mut())
+            .find(|w| w.notification.id == id && !w.marked_for_destroy);
+
+        if let Some(window) = window {
+            close_or_ungroup(window, CloseReason::Closed, &mut self.history);
             self.dirty = true;
         }
+
+        // Notifications grouped behind another one are taken out of the group.
+        for window in self.monitor_windows.values_mut().flat_map(|windows| windows.iter_mut()) {
+            if let Some(idx) = window.grouped.iter().position(|(n, _)| n.id == id) {
+                let (notification, _) = window.grouped.remove(idx);
+                dbus::signal_notification_closed(id, CloseReason::Closed);
+                self.history.push_back(notification);
+                // `%{count}` might be showing.
+                window.relayout();
+                self.dirty = true;
+            }
+        }
+
+        // Notifications on a card are taken off it, and the card shrinks to fit.
+        for window in self.monitor_windows.values_mut().flat_map(|windows| windows.iter_mut()) {
+            if let Some(idx) = window.combined.iter().position(|entry| entry.notification.id == id) {
+                let entry = window.combined.remove(idx);
+                dbus::signal_notification_closed(id, CloseReason::Closed);
+                self.history.push_back(entry.notification);
+                window.relayout();
+                self.dirty = true;
+            }
+        }
+
+        // Notifications can be closed before they've had a chance to be shown, see
+        // `Config::overflow`.
+        if let Some(idx) = self.overflow.iter().position(|n| n.id == id) {
+            let notification = self.overflow.remove(idx).unwrap();
+            dbus::signal_notification_closed(id, CloseReason::Closed);
+            self.history.push_back(notification);
+        }
+    }
+
+    // Dismiss the notification that arrived most recently, as if the user had closed it.
+    pub fn dismiss_last(&mut self) {
+        let newest = self.monitor_windows.values_mut()
+            .flat_map(|windows| windows.iter_mut())
+            .filter(|w| !w.marked_for_destroy && w.notification.id != OVERFLOW_INDICATOR_ID)
+            .max_by_key(|w| w.notification.time);
+
+        if let Some(window) = newest {
+            self.last_dismissed = Some(window.notification.id);
+            close_or_ungroup(window, CloseReason::Dismissed, &mut self.history);
+            self.dirty = true;
+        }
+    }
+
+    // Dismiss every notification, including any held back by `Config::overflow`.
+    pub fn dismiss_all(&mut self) {
+        // Nothing's held back after this, so the "+N more" notification is closed along with the
+        // rest, without being reported or remembered (see `remove_destroyed_windows()`).
+        for notification in self.overflow.drain(..) {
+            dbus::signal_notification_closed(notification.id, CloseReason::Dismissed);
+            self.history.push_back(notification);
+        }
+
+        self.drop_windows();
     }
 
     // @TODO: how about a shortcut for dropping all windows on one monitor?
     pub fn drop_windows(&mut self) {
         for (_monitor, windows) in &mut self.monitor_windows {
             for window in windows.iter_mut() {
-                window.marked_for_destroy = true;
+                window.close(CloseReason::Dismissed);
                 self.dirty = true;
             }
         }
@@ -245,6 +1384,8 @@ impl NotifyWindowManager {
                 .get(&monitor).unwrap()
                 .get(idx).unwrap();
 
+            window.draw();
+        } else if let Some(window) = self.center.iter().find(|w| w.winit().id() == window_id) {
             window.draw();
         }
     }
@@ -298,3 +1439,258 @@ fn find_and_open_url(string: String) {
         }
     }
 }
+
+// Copy the summary and body of a notification to the clipboard, as plain text.
+// Returns whether the copy command could be started.
+fn copy_to_clipboard(notification: &Notification) -> bool {
+    let command = &Config::get().copy_command;
+    if command.is_empty() {
+        return false;
+    }
+
+    let plain = |markup: &str| {
+        pango::parse_markup(markup, '\0')
+            .map(|(_, text, _)| text.to_string())
+            .unwrap_or_else(|_| markup.to_owned())
+    };
+    let text = match (plain(&notification.summary), plain(&notification.body)) {
+        (summary, body) if body.is_empty() => summary,
+        (summary, body) => format!("{}\n{}", summary, body),
+    };
+
+    let child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            // Dropping stdin closes it, so the command knows it has everything.
+            if let Some(mut stdin) = child.stdin.take() {
+                if let Err(e) = stdin.write_all(text.as_bytes()) {
+                    eprintln!("Couldn't write to \"{}\": {}", command[0], e);
+                    return false;
+                }
+            }
+            true
+        },
+        Err(e) => {
+            eprintln!("Tried to copy a notification using \"{}\", but the command failed: {}", command[0], e);
+            false
+        },
+    }
+}
+
+// Put queued notifications (oldest first) in the order they should be shown in.
+fn order_queue(queued: &mut Vec<Notification>, order: &QueueOrder) {
+    match order {
+        QueueOrder::Fifo => {},
+        QueueOrder::Lifo => queued.reverse(),
+        // The sort is stable, so notifications with the same urgency stay oldest first.
+        QueueOrder::Priority => queued.sort_by(|a, b| b.urgency.partial_cmp(&a.urgency).unwrap()),
+    }
+}
+
+// Move windows off monitors which have gone (`old` is the monitor layout before the change, and `new`
+// after it), or whose index now refers to a different monitor, onto the primary monitor after the
+// windows already there.  Otherwise they'd be placed on the primary monitor anyway (see
+// `update_positions()`), but as a second stack on top of the first.
+fn migrate_windows<T>(monitor_windows: &mut HashMap<u32, Vec<T>>, old: &[Rect], new: &[Rect], primary: u32) {
+    let mut stale: Vec<u32> = monitor_windows.keys()
+        .copied()
+        .filter(|&monitor| {
+            let idx = monitor as usize;
+            monitor != primary && (idx >= new.len() || old.get(idx) != new.get(idx))
+        })
+        .collect();
+    // Keep the order the same whichever way the map is iterated.
+    stale.sort();
+
+    for monitor in stale {
+        let windows = monitor_windows.remove(&monitor).unwrap();
+        monitor_windows.entry(primary).or_insert(vec![]).extend(windows);
+    }
+}
+
+// Close the notification a window is showing.  If others are grouped behind it (see
+// `Config::group_by`), the next newest is shown instead of closing the window.
+fn close_or_ungroup(window: &mut NotifyWindow, reason: CloseReason, history: &mut VecDeque<Notification>) {
+    match window.pop_grouped() {
+        Some(shown) => {
+            dbus::signal_notification_closed(shown.id, reason);
+            history.push_back(shown);
+        },
+        None => window.close(reason),
+    }
+}
+
+// Play a notification's sound, without waiting for it to finish.
+fn play_sound(sound: &Sound) {
+    let (program, args) = match sound {
+        Sound::File(path) => {
+            if !Path::new(path).exists() {
+                eprintln!("Warning: sound file \"{}\" doesn't exist.", path);
+                return;
+            }
+
+            ("paplay", vec![path.as_str()])
+        },
+        Sound::Name(name) => ("canberra-gtk-play", vec!["-i", name.as_str()]),
+    };
+
+    let child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    if let Err(e) = child {
+        eprintln!("Tried to play a notification sound using \"{}\", but the command failed: {}", program, e);
+    }
+}
+
+// Run the text to speech command for a notification.
+fn announce_notification(announce: &AnnounceConfig, notification: &Notification) {
+    if notification.urgency < announce.min_urgency || announce.command.is_empty() {
+        return;
+    }
+
+    // Speech should be plain text.
+    let plain = |markup: &str| {
+        pango::parse_markup(markup, '\0')
+            .map(|(_, text, _)| text.to_string())
+            .unwrap_or_else(|_| markup.to_owned())
+    };
+    let (summary, body) = (plain(&notification.summary), plain(&notification.body));
+
+    // Substitute in one pass, so text from the notification is never substituted again.
+    let substitute = |arg: &str| {
+        let mut out = String::new();
+        let mut rest = arg;
+        while let Some(idx) = rest.find('%') {
+            out.push_str(&rest[..idx]);
+            let token = &rest[idx..];
+            let value = if token.starts_with("%a") {
+                Some(notification.app_name.as_str())
+            } else if token.starts_with("%s") {
+                Some(summary.as_str())
+            } else if token.starts_with("%b") {
+                Some(body.as_str())
+            } else {
+                None
+            };
+
+            match value {
+                Some(v) => { out.push_str(v); rest = &token[2..]; },
+                None => { out.push('%'); rest = &token[1..]; },
+            }
+        }
+        out.push_str(rest);
+        out
+    };
+    let args: Vec<String> = announce.command.iter().map(|arg| substitute(arg)).collect();
+
+    // Like `xdg-open` above, we don't wait for the command, so speech doesn't hold anything up.
+    let child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    if let Err(e) = child {
+        eprintln!("Tried to announce a notification using \"{}\", but the command failed: {}", args[0], e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::dbus::Value;
+
+    // Ids are in order of arrival.
+    fn queue(urgencies: &[u8]) -> Vec<Notification> {
+        Config::init_for_tests();
+        urgencies.iter().enumerate()
+            .map(|(i, &urgency)| {
+                let mut hints = HashMap::new();
+                hints.insert("urgency".to_owned(), Value::U8(urgency));
+                Notification::from_dbus(i as u32 + 1, "app", 0, "", "Summary", "Body", vec![], hints, -1)
+            })
+            .collect()
+    }
+
+    fn ordered_ids(order: QueueOrder) -> Vec<u32> {
+        let mut queued = queue(&[1, 0, 2, 1, 2, 0]);
+        order_queue(&mut queued, &order);
+        queued.iter().map(|n| n.id).collect()
+    }
+
+    #[test]
+    fn fifo_keeps_arrival_order() {
+        assert_eq!(ordered_ids(QueueOrder::Fifo), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn lifo_is_newest_first() {
+        assert_eq!(ordered_ids(QueueOrder::Lifo), vec![6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn priority_is_most_urgent_first() {
+        // Critical, then normal, then low, each oldest first.
+        assert_eq!(ordered_ids(QueueOrder::Priority), vec![3, 5, 1, 4, 2, 6]);
+    }
+
+    fn monitor(x: f64) -> Rect {
+        Rect::new(x, 0.0, 1920.0, 1080.0)
+    }
+
+    // Windows on three monitors, with the primary monitor first.
+    fn three_monitors() -> (Vec<Rect>, HashMap<u32, Vec<&'static str>>) {
+        let mut windows = HashMap::new();
+        windows.insert(0, vec!["a", "b"]);
+        windows.insert(1, vec!["c"]);
+        windows.insert(2, vec!["d", "e"]);
+        (vec![monitor(0.0), monitor(1920.0), monitor(3840.0)], windows)
+    }
+
+    #[test]
+    fn unplugged_monitor_windows_move_to_primary() {
+        let (old, mut windows) = three_monitors();
+        // Unplugging the middle monitor renumbers the last one, which would otherwise be placed as
+        // if it were still the middle one.
+        let new = vec![monitor(0.0), monitor(3840.0)];
+        migrate_windows(&mut windows, &old, &new, 0);
+
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[&0], vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn unchanged_monitor_windows_stay() {
+        let (old, mut windows) = three_monitors();
+        let mut new = old.clone();
+        new.push(monitor(5760.0));
+        migrate_windows(&mut windows, &old, &new, 0);
+
+        assert_eq!(windows[&0], vec!["a", "b"]);
+        assert_eq!(windows[&1], vec!["c"]);
+        assert_eq!(windows[&2], vec!["d", "e"]);
+    }
+
+    #[test]
+    fn unplugged_primary_windows_join_new_primary() {
+        let (old, mut windows) = three_monitors();
+        // The second monitor becomes the primary one, and is now first.
+        let new = vec![monitor(1920.0), monitor(3840.0)];
+        migrate_windows(&mut windows, &old, &new, 0);
+
+        // The old primary monitor's windows are already keyed to the new one.
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[&0], vec!["a", "b", "c", "d", "e"]);
+    }
+}
@@ -1,14 +1,105 @@
 #![allow(dead_code)]
 
-use serde::Deserialize;
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
 
-#[derive(Default, Debug, Deserialize, Clone)]
-pub struct MinMax {
-    pub min: i32,
-    pub max: i32,
+// Blocks configure sizes as `MinMax<Length>`, which is resolved to pixels once the monitor is known.
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct MinMax<T = i32> {
+    pub min: T,
+    pub max: T,
 }
 
-#[derive(Default, Debug, Clone, Deserialize)]
+impl MinMax<Length> {
+    pub fn resolve(&self, extent: f64) -> MinMax {
+        MinMax { min: self.min.resolve_size(extent), max: self.max.resolve_size(extent) }
+    }
+}
+
+// A distance in pixels, or a percentage of the size of whatever it's relative to (the monitor for
+// block sizes, the parent block for offsets), written as `300` or `"30%"`.
+#[derive(Debug, Clone)]
+pub enum Length {
+    Pixels(f64),
+    Percent(f64),
+}
+
+impl Default for Length {
+    fn default() -> Self { Length::Pixels(0.0) }
+}
+
+impl Length {
+    // Resolve to pixels, given the size along the same axis.
+    pub fn resolve(&self, extent: f64) -> f64 {
+        match self {
+            Length::Pixels(px) => *px,
+            Length::Percent(pc) => extent * pc / 100.0,
+        }
+    }
+
+    // Resolve a block size to pixels, given the monitor's size along the same axis.  Negative pixel
+    // values mean "unlimited" for maximums, so they're left alone; anything else is kept within the
+    // monitor.
+    pub fn resolve_size(&self, extent: f64) -> i32 {
+        match self {
+            Length::Pixels(px) if *px < 0.0 => *px as i32,
+            Length::Pixels(px) => (*px as i32).min(extent as i32),
+            Length::Percent(pc) => (extent * pc.max(0.0).min(100.0) / 100.0) as i32,
+        }
+    }
+
+    pub fn negated(&self) -> Self {
+        match self {
+            Length::Pixels(px) => Length::Pixels(-px),
+            Length::Percent(pc) => Length::Percent(-pc),
+        }
+    }
+}
+
+impl Serialize for Length {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Length::Pixels(px) => serializer.serialize_f64(*px),
+            Length::Percent(pc) => serializer.serialize_str(&format!("{}%", pc)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Length {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Len {
+            Pixels(f64),
+            Percent(String),
+        }
+
+        match Len::deserialize(deserializer) {
+            Ok(Len::Pixels(px)) => Ok(Length::Pixels(px)),
+            Ok(Len::Percent(s)) => parse_percentage(&s)
+                .map(Length::Percent)
+                .ok_or_else(|| de::Error::custom(format!("invalid percentage \"{}\"", s))),
+            Err(_) => Err(de::Error::custom("expected a number of pixels, or a percentage like \"10%\"")),
+        }
+    }
+}
+
+// Parse a percentage like "10%" to `10.0`.
+pub fn parse_percentage(s: &str) -> Option<f64> {
+    let trimmed = s.trim();
+    if !trimmed.ends_with('%') {
+        return None;
+    }
+
+    trimmed.trim_end_matches('%').trim().parse::<f64>().ok()
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Vec2 {
     pub x: f64,
     pub y: f64,
@@ -20,34 +111,36 @@ impl Vec2 {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rect {
     x: f64,
     y: f64,
     width: f64,
     height: f64,
+    // Distance from the top of the rect to the text baseline, for rects which contain text.
+    baseline: Option<f64>,
 }
 
 impl Default for Rect {
     fn default() -> Self {
         Self {
-            x: 0.0, y: 0.0, width: 0.0, height: 0.0,
+            x: 0.0, y: 0.0, width: 0.0, height: 0.0, baseline: None,
         }
     }
 }
 
 impl Rect {
-    pub const EMPTY: Self = Self { x: 0.0, y: 0.0, width: 0.0, height: 0.0 };
+    pub const EMPTY: Self = Self { x: 0.0, y: 0.0, width: 0.0, height: 0.0, baseline: None };
 
     pub fn empty() -> Self {
         Self {
-            x: 0.0, y: 0.0, width: 0.0, height: 0.0,
+            x: 0.0, y: 0.0, width: 0.0, height: 0.0, baseline: None,
         }
     }
 
     pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
         Self {
-            x, y, width, height,
+            x, y, width, height, baseline: None,
         }
     }
 
@@ -71,6 +164,11 @@ impl Rect {
         (self.width(), self.height())
     }
 
+    // Rects without text are treated as if their baseline is the bottom edge.
+    pub fn baseline(&self) -> f64 {
+        self.baseline.unwrap_or(self.height)
+    }
+
     pub fn set_x(&mut self, x: f64) {
         self.x = x;
     }
@@ -92,6 +190,10 @@ impl Rect {
         self.height = height;
     }
 
+    pub fn set_baseline(&mut self, baseline: f64) {
+        self.baseline = Some(baseline);
+    }
+
     pub fn left(&self) -> f64 {
         self.x
     }
@@ -140,6 +242,19 @@ impl Rect {
         self.y = bottom - self.height
     }
 
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.left() < other.right() && other.left() < self.right() &&
+        self.top() < other.bottom() && other.top() < self.bottom()
+    }
+
+    // Move the rect so that it's inside `bounds`, as far as possible.  If the rect is larger than
+    // `bounds`, it will be aligned with the top left.
+    pub fn clamp_within(&mut self, bounds: &Rect) {
+        let x = clamp(self.x, bounds.left(), bounds.right() - self.width).max(bounds.left());
+        let y = clamp(self.y, bounds.top(), bounds.bottom() - self.height).max(bounds.top());
+        self.set_xy(x, y);
+    }
+
     pub fn union_new(&self, other: &Rect) -> Rect {
         let x = f64::min(self.x(), other.x());
         let y = f64::min(self.y(), other.y());
@@ -202,6 +317,37 @@ pub fn cairo_rounded_rectangle(ctx: &cairo::Context, x: f64, y: f64, width: f64,
     ctx.restore();
 }
 
+// Cairo can't blur, so soft shadows are a stack of translucent rounded rects, each one smaller than
+// the last, which is close enough to a blur for something this soft.  `rect` is the shape casting
+// the shadow, which is moved by `offset` and spread out by `blur`.
+pub fn cairo_soft_shadow(
+    ctx: &cairo::Context,
+    rect: &Rect,
+    corner_radius: f64,
+    offset: &Vec2,
+    blur: f64,
+    color: &crate::config::Color,
+) {
+    let steps = (blur.ceil() as usize).max(1).min(16);
+
+    ctx.save();
+    ctx.set_operator(cairo::Operator::Over);
+    ctx.set_source_rgba(color.r, color.g, color.b, color.a / steps as f64);
+    for i in 0..steps {
+        let spread = blur * (1.0 - i as f64 / steps as f64);
+        cairo_rounded_rectangle(
+            ctx,
+            rect.x() + offset.x - spread,
+            rect.y() + offset.y - spread,
+            rect.width() + spread * 2.0,
+            rect.height() + spread * 2.0,
+            corner_radius + spread,
+        );
+        ctx.fill();
+    }
+    ctx.restore();
+}
+
 pub fn debug_rect(ctx: &cairo::Context, alt: bool, x: f64, y: f64, width: f64, height: f64) {
     use crate::config::Config;
     // Often, modules will check for debug before calling this anyway to save work, but it's good
@@ -225,6 +371,70 @@ pub fn debug_rect(ctx: &cairo::Context, alt: bool, x: f64, y: f64, width: f64, h
     ctx.restore();
 }
 
+// Byte ranges of every URL (a word starting with "http://" or "https://") in some text.
+pub fn find_urls(text: &str) -> Vec<(usize, usize)> {
+    let mut urls = vec![];
+    let mut start = 0;
+    for word in text.split_whitespace() {
+        // `split_whitespace()` doesn't give us indices, so find where this word is.
+        let word_start = start + text[start..].find(word).unwrap();
+        start = word_start + word.len();
+
+        if word.starts_with("http://") || word.starts_with("https://") {
+            urls.push((word_start, start));
+        }
+    }
+
+    urls
+}
+
+// Collapse each run of whitespace into a single space, or a single newline if the run had any line
+// breaks in it.  Whitespace at the start and end is removed.
+pub fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    // The whitespace run we're in, if any: whether it contained a line break.
+    let mut run: Option<bool> = None;
+    for c in text.trim().chars() {
+        if c.is_whitespace() {
+            let line_break = match c {
+                '\n' | '\r' | '\u{0085}' | '\u{2028}' | '\u{2029}' => true,
+                _ => false,
+            };
+            run = Some(run.unwrap_or(false) || line_break);
+            continue;
+        }
+
+        if let Some(line_break) = run.take() {
+            collapsed.push(if line_break { '\n' } else { ' ' });
+        }
+        collapsed.push(c);
+    }
+
+    collapsed
+}
+
+// Deal with control characters (other than newlines and tabs), which can break Pango or show up as
+// boxes, according to `mode`.
+pub fn sanitize_control_chars(text: &str, mode: &crate::config::ControlCharacters) -> String {
+    use crate::config::ControlCharacters;
+
+    let is_unwanted = |c: char| c.is_control() && c != '\n' && c != '\t';
+    match mode {
+        ControlCharacters::Keep => text.to_owned(),
+        ControlCharacters::Strip => text.chars().filter(|c| !is_unwanted(*c)).collect(),
+        // C0 controls and DEL have their own symbols in the "Control Pictures" block, e.g. "␀" for
+        // NUL.  Anything else gets the replacement character.
+        ControlCharacters::Replace => text.chars()
+            .map(|c| match c {
+                c if !is_unwanted(c) => c,
+                '\u{0}'..='\u{1f}' => std::char::from_u32(0x2400 + c as u32).unwrap_or('\u{fffd}'),
+                '\u{7f}' => '\u{2421}',
+                _ => '\u{fffd}',
+            })
+            .collect(),
+    }
+}
+
 pub fn escape_decode(to_escape: &str) -> String {
     // Escape ampersand and decode some html stuff manually, for fun.
     // can escape about 6 ampersands without allocating (each is 4 chars, minus the existing char).
@@ -268,13 +478,57 @@ pub fn escape_decode(to_escape: &str) -> String {
     String::from_utf8(escaped).expect("Error when escaping ampersand.")
 }
 
+// Escape everything Pango would read as markup, so the text is shown exactly as it was sent.
+pub fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
 use crate::bus::dbus::Notification;
+use crate::config::Config;
+use crate::rendering::window::NotifyWindow;
 
 // str.replace() won't work for this because we'd have to do it twice: once for the summary and
 // once for the body.  The first insertion could insert format strings which would mess up the
 // second insertion.
 // This solution is pretty fast (microseconds in release).
-pub fn format_notification_string(format_string: &str, notification: &Notification) -> String {
+//
+// `%{remaining}` is the number of seconds until the notification expires, or
+// `Config::remaining_placeholder` if it never does.  `%{elapsed}` is the number of seconds since it
+// was shown.  Strings containing either change over time, see `is_time_dependent()`.
+// `%a` is the app name and `%c` is the number of notifications in this notification's app group
+// (see `Config::stack_by_app`).
+// `%{count}` is the number of notifications grouped in this notification's window (see
+// `Config::group_by`).
+//
+// Besides `%` tokens, a small template syntax is supported:
+// - `{summary}`, `{body}`, `{app}` insert the matching notification field.
+// - `{default_action}` inserts the label of the notification's default action, if it has one.
+//   This is handy for hinting that a notification can be clicked, e.g.
+//   `{if default_action}Click to open{endif}`.
+// - `{if field}...{else}...{endif}` selects a branch based on whether the field is non-empty.
+//   Conditionals can be nested, and `{else}` is optional.
+// If the template can't be parsed, it is used literally.
+pub fn format_notification_string(format_string: &str, window: &NotifyWindow) -> String {
+    let notification = &window.notification;
+
+    // Conditionals are resolved on the template before anything is substituted, so that inserted
+    // text can never be mistaken for template syntax.
+    let resolved = resolve_template(format_string, notification);
+    let format_string = resolved.as_str();
+
     let mut formatted: Vec<u8> = vec![];
     let bytes = format_string.as_bytes();
     let mut i = 0;
@@ -291,6 +545,34 @@ pub fn format_notification_string(format_string: &str, notification: &Notificati
         match byte {
             b'%' => {
                 // This range is exclusive on the right hand side, so we go +2.
+                if format_string[i..].starts_with("%{remaining}") {
+                    if notification.never_expires() {
+                        formatted.extend_from_slice(Config::get().remaining_placeholder.as_bytes());
+                    } else {
+                        // Round up, so we don't show 0 while the notification is still around.
+                        let remaining = (window.fuse.max(0) + 999) / 1000;
+                        formatted.extend_from_slice(remaining.to_string().as_bytes());
+                    }
+                    i += "%{remaining}".len();
+                    continue;
+                }
+                if format_string[i..].starts_with("%{elapsed}") {
+                    // Notifications without a fuse count from when their window was created.
+                    let elapsed = if notification.never_expires() {
+                        window.lifetime.as_secs()
+                    } else {
+                        ((notification.timeout - window.fuse).max(0) / 1000) as u64
+                    };
+                    formatted.extend_from_slice(elapsed.to_string().as_bytes());
+                    i += "%{elapsed}".len();
+                    continue;
+                }
+                if format_string[i..].starts_with("%{count}") {
+                    formatted.extend_from_slice((window.grouped.len() + 1).to_string().as_bytes());
+                    i += "%{count}".len();
+                    continue;
+                }
+
                 match &format_string[i..i+2] {
                     // We need room for at least 2 brackets, so check for that.
                     "%t" => if i+4 < format_string.len() {
@@ -306,12 +588,26 @@ pub fn format_notification_string(format_string: &str, notification: &Notificati
                     }
                     "%s" => { formatted.extend_from_slice(notification.summary.as_bytes()); i += 2; continue },
                     "%b" => { formatted.extend_from_slice(notification.body.as_bytes()); i += 2; continue },
+                    "%a" => { formatted.extend_from_slice(notification.app_name.as_bytes()); i += 2; continue },
+                    "%c" => { formatted.extend_from_slice(window.group.count.to_string().as_bytes()); i += 2; continue },
                     _ => (),
                 }
 
                 formatted.push(b'%');
             }
 
+            b'{' => {
+                if let Some(close_idx) = format_string[i..].find('}') {
+                    if let Some(field) = notification_field(&format_string[i+1..i+close_idx], notification) {
+                        formatted.extend_from_slice(field.as_bytes());
+                        i += close_idx + 1;
+                        continue;
+                    }
+                }
+
+                formatted.push(b'{');
+            }
+
             _ => formatted.push(byte),
         }
 
@@ -322,6 +618,101 @@ pub fn format_notification_string(format_string: &str, notification: &Notificati
     String::from_utf8(formatted).expect("Error when formatting notification string.")
 }
 
+// Whether a format string contains tokens which change as time passes, meaning it needs to be
+// formatted again on update.
+pub fn is_time_dependent(format_string: &str) -> bool {
+    format_string.contains("%{remaining}") || format_string.contains("%{elapsed}")
+}
+
+// Get a notification field by the name used in templates.
+fn notification_field<'a>(name: &str, notification: &'a Notification) -> Option<&'a str> {
+    match name {
+        "summary" => Some(&notification.summary),
+        "body" => Some(&notification.body),
+        "app" => Some(&notification.app_name),
+        "default_action" => Some(notification.get_default_action().unwrap_or("")),
+        _ => None,
+    }
+}
+
+// Resolve a template's conditionals, or use it literally if it can't be parsed.
+fn resolve_template(template: &str, notification: &Notification) -> String {
+    match resolve_conditionals(template, notification) {
+        Ok(r) => r,
+        Err(e) => {
+            if first_parse_failure(template) {
+                eprintln!("Warning: couldn't parse template \"{}\", so will use it literally: {}.", template, e);
+            }
+            template.to_owned()
+        },
+    }
+}
+
+// Whether a template which can't be parsed hasn't been seen before.  Templates are formatted
+// whenever a layout is predicted (and every update, for time dependent text), so each one is only
+// warned about once.
+fn first_parse_failure(template: &str) -> bool {
+    thread_local! {
+        static FAILED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    }
+
+    FAILED.with(|failed| failed.borrow_mut().insert(template.to_owned()))
+}
+
+// Strip `{if field}`, `{else}`, and `{endif}` tags from a template, keeping only the branches
+// that apply to this notification.  Other `{...}` tags are left alone for substitution.
+fn resolve_conditionals(template: &str, notification: &Notification) -> Result<String, &'static str> {
+    // Each entry is (condition, in_else) for an open `{if}`.  Text is kept when every open
+    // conditional is in the branch that was selected.
+    let mut stack: Vec<(bool, bool)> = vec![];
+    let mut resolved = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open_idx) = rest.find('{') {
+        let active = stack.iter().all(|&(cond, in_else)| cond != in_else);
+        if active {
+            resolved.push_str(&rest[..open_idx]);
+        }
+
+        let close_idx = match rest[open_idx..].find('}') {
+            Some(idx) => open_idx + idx,
+            None => {
+                // No more tags, so the rest is just text.
+                if active {
+                    resolved.push_str(&rest[open_idx..]);
+                }
+                rest = "";
+                break;
+            },
+        };
+
+        let tag = &rest[open_idx+1..close_idx];
+        if tag.starts_with("if ") {
+            let field = notification_field(tag[3..].trim(), notification)
+                .ok_or("unknown field in `{if}`")?;
+            stack.push((!field.is_empty(), false));
+        } else if tag == "else" {
+            match stack.last_mut() {
+                Some(frame) if !frame.1 => frame.1 = true,
+                _ => return Err("`{else}` without a matching `{if}`"),
+            }
+        } else if tag == "endif" {
+            stack.pop().ok_or("`{endif}` without a matching `{if}`")?;
+        } else if active {
+            resolved.push_str(&rest[open_idx..=close_idx]);
+        }
+
+        rest = &rest[close_idx+1..];
+    }
+
+    if !stack.is_empty() {
+        return Err("`{if}` without a matching `{endif}`");
+    }
+
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
 // This function expects a string that has an open bracket to start, and a closing bracket
 // *somewhere*.  It will return the string between the open bracket and the first closing bracket.
 fn extract_time_format(string: &str) -> Option<(&str, usize)> {
@@ -344,3 +735,120 @@ fn extract_time_format(string: &str) -> Option<(&str, usize)> {
         return None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::ControlCharacters;
+
+    fn notification(summary: &str, body: &str) -> Notification {
+        Config::init_for_tests();
+        Notification::from_dbus(1, "app", 0, "", summary, body, vec![], HashMap::new(), -1)
+    }
+
+    #[test]
+    fn conditional_picks_branch() {
+        let template = "{if body}{body}{else}{summary}{endif}";
+        assert_eq!(resolve_conditionals(template, &notification("Title", "Text")).unwrap(), "{body}");
+        assert_eq!(resolve_conditionals(template, &notification("Title", "")).unwrap(), "{summary}");
+    }
+
+    #[test]
+    fn conditional_without_else() {
+        let template = "a{if body} - {body}{endif}b";
+        assert_eq!(resolve_conditionals(template, &notification("Title", "Text")).unwrap(), "a - {body}b");
+        assert_eq!(resolve_conditionals(template, &notification("Title", "")).unwrap(), "ab");
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let template = "{if summary}S{if body}B{else}-{endif}{else}{if body}b{endif}{endif}";
+        assert_eq!(resolve_conditionals(template, &notification("Title", "Text")).unwrap(), "SB");
+        assert_eq!(resolve_conditionals(template, &notification("Title", "")).unwrap(), "S-");
+        assert_eq!(resolve_conditionals(template, &notification("", "Text")).unwrap(), "b");
+        assert_eq!(resolve_conditionals(template, &notification("", "")).unwrap(), "");
+    }
+
+    #[test]
+    fn other_tags_and_braces_are_kept() {
+        let n = notification("Title", "Text");
+        assert_eq!(resolve_conditionals("{app}: {summary} %s", &n).unwrap(), "{app}: {summary} %s");
+        assert_eq!(resolve_conditionals("open { brace", &n).unwrap(), "open { brace");
+    }
+
+    #[test]
+    fn malformed_conditionals_are_errors() {
+        let n = notification("Title", "Text");
+        assert!(resolve_conditionals("{if body}unterminated", &n).is_err());
+        assert!(resolve_conditionals("{else}", &n).is_err());
+        assert!(resolve_conditionals("{if body}a{else}b{else}c{endif}", &n).is_err());
+        assert!(resolve_conditionals("{endif}", &n).is_err());
+        assert!(resolve_conditionals("{if nonsense}a{endif}", &n).is_err());
+    }
+
+    #[test]
+    fn malformed_template_is_used_literally() {
+        let n = notification("Title", "Text");
+        assert_eq!(resolve_template("{if body}unterminated", &n), "{if body}unterminated");
+        assert_eq!(resolve_template("{if body}ok{endif}", &n), "ok");
+    }
+
+    #[test]
+    fn malformed_template_is_warned_about_once() {
+        assert!(first_parse_failure("{if summary}once"));
+        assert!(!first_parse_failure("{if summary}once"));
+        assert!(first_parse_failure("{if summary}twice"));
+    }
+
+    #[test]
+    fn control_chars_strip() {
+        let text = "a\u{0}b\u{8}c\u{1b}[1md\u{7f}e\u{85}f";
+        assert_eq!(sanitize_control_chars(text, &ControlCharacters::Strip), "abc[1mdef");
+    }
+
+    #[test]
+    fn control_chars_replace() {
+        let text = "a\u{0}b\u{8}c\u{7f}d\u{85}e";
+        assert_eq!(sanitize_control_chars(text, &ControlCharacters::Replace), "a\u{2400}b\u{2408}c\u{2421}d\u{fffd}e");
+    }
+
+    #[test]
+    fn control_chars_keep() {
+        let text = "a\u{0}b\u{8}c";
+        assert_eq!(sanitize_control_chars(text, &ControlCharacters::Keep), text);
+    }
+
+    #[test]
+    fn control_chars_keep_newlines_and_tabs() {
+        let text = "line one\n\tline two\r";
+        assert_eq!(sanitize_control_chars(text, &ControlCharacters::Strip), "line one\n\tline two");
+        assert_eq!(sanitize_control_chars(text, &ControlCharacters::Replace), "line one\n\tline two\u{240d}");
+    }
+
+    #[test]
+    fn control_chars_stripped_from_notifications() {
+        // The default config strips them.
+        let n = notification("Sum\u{0}mary", "Bo\u{8}dy\nmore");
+        assert_eq!(n.summary, "Summary");
+        assert_eq!(n.body, "Body\nmore");
+    }
+
+    #[test]
+    fn lengths() {
+        let parse = |s| ron::de::from_str::<Length>(s).unwrap();
+        assert_eq!(parse("300").resolve_size(1000.0), 300);
+        assert_eq!(parse("2000.0").resolve_size(1000.0), 1000);
+        assert_eq!(parse("-1").resolve_size(1000.0), -1);
+        assert_eq!(parse("\"30%\"").resolve_size(1000.0), 300);
+        assert_eq!(parse("\" 150 % \"").resolve_size(1000.0), 1000);
+
+        assert_eq!(parse("-7.5").resolve(1000.0), -7.5);
+        assert_eq!(parse("\"10%\"").resolve(200.0), 20.0);
+        assert_eq!(parse("\"10%\"").negated().resolve(200.0), -20.0);
+
+        assert!(ron::de::from_str::<Length>("\"10\"").is_err());
+        assert!(ron::de::from_str::<Length>("\"ten%\"").is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use std::fs;
+use std::path::Path;
+
+// Whether the machine is currently running on battery power.
+// We check sysfs directly rather than going through upower to avoid another dbus dependency; any
+// battery which reports that it's discharging means we're on battery.
+pub fn on_battery() -> bool {
+    let supplies = match fs::read_dir("/sys/class/power_supply") {
+        Ok(supplies) => supplies,
+        // No power supply info (e.g. a VM or a desktop without sysfs), so assume we're on AC.
+        Err(_) => return false,
+    };
+
+    supplies
+        .filter_map(|entry| entry.ok())
+        .any(|entry| {
+            let path = entry.path();
+            read_trimmed(&path.join("type")).as_deref() == Some("Battery") &&
+            read_trimmed(&path.join("status")).as_deref() == Some("Discharging")
+        })
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
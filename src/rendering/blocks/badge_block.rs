@@ -0,0 +1,94 @@
+use serde::{Serialize, Deserialize};
+
+use crate::maths_utility::{self, Vec2, Rect};
+use crate::config::{Padding, Color, Operator};
+use crate::rendering::{
+    window::NotifyWindow,
+    layout::{DrawableLayoutElement, LayoutBlock, Hook},
+    text::EllipsizeMode,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BadgeBlockParameters {
+    // Name of the numeric hint to read the count from, e.g. "x-unread-count".
+    pub hint: String,
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
+    pub padding: Padding,
+    pub font: String,
+    pub color: Color,
+    pub text_color: Color,
+    // Minimum diameter of the badge.  The badge will grow into a pill for wide numbers.
+    #[serde(default)]
+    pub min_size: f64,
+    // How the badge is composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+
+    #[serde(skip)]
+    real_text: String,
+    #[serde(skip)]
+    badge_rect: Rect,
+}
+
+impl DrawableLayoutElement for BadgeBlockParameters {
+    fn draw(&self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        // Badge is hidden when there's nothing to count.
+        if self.real_text.is_empty() {
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &Rect::EMPTY);
+            return Rect::new(pos.x, pos.y, 0.0, 0.0);
+        }
+
+        window.context.set_operator(self.operator.to_cairo());
+
+        let mut rect = Rect::new(
+            0.0, 0.0,
+            self.badge_rect.width() + self.padding.width(),
+            self.badge_rect.height() + self.padding.height(),
+        );
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+
+        // Background -- a circle, or a pill if the number is wider than it is tall.
+        let (x, y) = (pos.x + self.padding.left, pos.y + self.padding.top);
+        let (width, height) = self.badge_rect.size();
+        window.context.set_source_rgba(self.color.r, self.color.g, self.color.b, self.color.a);
+        maths_utility::cairo_rounded_rectangle(&window.context, x, y, width, height, height / 2.0);
+        window.context.fill();
+
+        // Center the number in the badge.
+        window.text.set_text(&self.real_text, &self.font, -1, 0, &EllipsizeMode::NoEllipsize);
+        let text_rect = window.text.get_sized_rect(0, 0);
+        let text_pos = Vec2::new(
+            x + (width - text_rect.width()) / 2.0,
+            y + (height - text_rect.height()) / 2.0,
+        );
+        window.text.paint(&window.context, &text_pos, &self.text_color);
+
+        maths_utility::debug_rect(&window.context, true, x, y, width, height);
+
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+
+    fn predict_rect_and_init(&mut self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        let count = window.notification.get_hint_i32(&self.hint).unwrap_or(0);
+        if count == 0 {
+            self.real_text = String::new();
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &Rect::EMPTY);
+            return Rect::new(pos.x, pos.y, 0.0, 0.0);
+        }
+
+        self.real_text = count.to_string();
+        window.text.set_text(&self.real_text, &self.font, -1, 0, &EllipsizeMode::NoEllipsize);
+        let text_rect = window.text.get_sized_rect(0, 0);
+
+        let height = text_rect.height().max(self.min_size);
+        // Add a little horizontal room so the number doesn't touch the rounded ends.
+        let width = (text_rect.width() + height / 2.0).max(height);
+        self.badge_rect = Rect::new(0.0, 0.0, width, height);
+
+        let mut rect = Rect::new(0.0, 0.0, width + self.padding.width(), height + self.padding.height());
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+}
@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+
+use serde::{Serialize, Deserialize};
+
+use crate::maths_utility::{self, Vec2, Rect};
+use crate::config::{Padding, Color, Operator};
+use crate::rendering::{
+    window::NotifyWindow,
+    layout::{DrawableLayoutElement, LayoutBlock, Hook},
+    text::EllipsizeMode,
+};
+
+// A row of buttons, one for each of the notification's actions.  Clicking a button invokes its
+// action.  The default action isn't shown, because clicking anywhere on the notification invokes it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ButtonBlockParameters {
+    // Padding around the whole row.
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
+    pub padding: Padding,
+    // Padding between each label and the edge of its button.
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
+    pub button_padding: Padding,
+    pub font: String,
+    pub text_color: Color,
+    pub background_color: Color,
+    // -- Optional fields.
+    // Horizontal space between buttons.
+    #[serde(default)]
+    pub spacing: f64,
+    #[serde(default)]
+    pub rounding: f64,
+    #[serde(default)]
+    pub border_width: f64,
+    pub border_color: Option<Color>,
+    // How the buttons are composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+
+    // Pairs of (action key, label markup), and the size of each button without padding.
+    #[serde(skip)]
+    buttons: Vec<(String, String, Vec2)>,
+    // Where each button was last drawn in device coordinates, for hit-testing clicks.  Positions can
+    // only be known when drawing (see the note on `LayoutElement`), hence the `RefCell`.
+    #[serde(skip)]
+    button_rects: RefCell<Vec<(String, Rect)>>,
+}
+
+impl ButtonBlockParameters {
+    fn row_rect(&self) -> Rect {
+        if self.buttons.is_empty() {
+            return Rect::EMPTY;
+        }
+
+        let width = self.buttons.iter()
+            .map(|(_, _, size)| size.x + self.button_padding.width())
+            .sum::<f64>() + self.spacing * (self.buttons.len() - 1) as f64;
+        let height = self.buttons.iter()
+            .map(|(_, _, size)| size.y + self.button_padding.height())
+            .fold(0.0, f64::max);
+
+        Rect::new(0.0, 0.0, width + self.padding.width(), height + self.padding.height())
+    }
+
+    // The key of the action whose button is under `pos`, in window coordinates.
+    pub fn action_at(&self, pos: &Vec2) -> Option<String> {
+        self.button_rects.borrow().iter()
+            .find(|(_, r)| pos.x >= r.left() && pos.x < r.right() && pos.y >= r.top() && pos.y < r.bottom())
+            .map(|(key, _)| key.clone())
+    }
+}
+
+impl DrawableLayoutElement for ButtonBlockParameters {
+    fn draw(&self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        let mut button_rects = self.button_rects.borrow_mut();
+        button_rects.clear();
+
+        let mut rect = self.row_rect();
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        if self.buttons.is_empty() {
+            return rect;
+        }
+
+        window.context.set_operator(self.operator.to_cairo());
+
+        let row_height = rect.height() - self.padding.height();
+        let mut x = pos.x + self.padding.left;
+        for (key, label, size) in &self.buttons {
+            let button = Rect::new(x, pos.y + self.padding.top, size.x + self.button_padding.width(), row_height);
+
+            let bg = &self.background_color;
+            window.context.set_source_rgba(bg.r, bg.g, bg.b, bg.a);
+            maths_utility::cairo_rounded_rectangle(
+                &window.context, button.x(), button.y(), button.width(), button.height(), self.rounding
+            );
+            window.context.fill_preserve();
+            if let Some(bc) = &self.border_color {
+                window.context.set_source_rgba(bc.r, bc.g, bc.b, bc.a);
+                window.context.set_line_width(self.border_width);
+                window.context.stroke();
+            } else {
+                window.context.new_path();
+            }
+
+            // Center the label in its button, in case the buttons are different heights.
+            window.text.set_text(label, &self.font, -1, -1, &EllipsizeMode::NoEllipsize);
+            let text_pos = Vec2::new(
+                button.x() + self.button_padding.left,
+                button.y() + (button.height() - size.y) / 2.0,
+            );
+            window.text.paint(&window.context, &text_pos, &self.text_color);
+            maths_utility::debug_rect(&window.context, true, button.x(), button.y(), button.width(), button.height());
+
+            x += button.width() + self.spacing;
+            let (x0, y0) = window.context.user_to_device(button.left(), button.top());
+            let (x1, y1) = window.context.user_to_device(button.right(), button.bottom());
+            button_rects.push((key.clone(), Rect::new(x0.min(x1), y0.min(y1), (x1 - x0).abs(), (y1 - y0).abs())));
+        }
+
+        rect
+    }
+
+    fn predict_rect_and_init(&mut self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        self.buttons = window.notification.actions.iter()
+            .filter(|(key, _)| key != "default")
+            .map(|(key, label)| {
+                let label = maths_utility::escape_decode(label);
+                window.text.set_text(&label, &self.font, -1, -1, &EllipsizeMode::NoEllipsize);
+                let text_rect = window.text.get_sized_rect(0, 0);
+                (key.clone(), label, Vec2::new(text_rect.width(), text_rect.height()))
+            })
+            .collect();
+
+        let mut rect = self.row_rect();
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+}
@@ -1,14 +1,18 @@
-use serde::Deserialize;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
 
 use crate::maths_utility::{self, Vec2, Rect};
-use crate::config::{Padding};
+use crate::config::{Padding, Color, Operator, Shadow};
 use crate::rendering::window::NotifyWindow;
-use image::FilterType;
+use crate::rendering::text::EllipsizeMode;
+use crate::rendering::blocks::text_block::Dimensions;
+use image::{DynamicImage, FilterType, GenericImageView};
 use cairo::ImageSurface;
 use cairo::Format;
 use crate::rendering::layout::{DrawableLayoutElement, LayoutBlock, Hook};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ImageType {
     App,
     Hint,
@@ -16,7 +20,7 @@ pub enum ImageType {
     HintThenApp,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum FilterMode {
     Nearest,
     Triangle,
@@ -38,50 +42,262 @@ impl FilterMode {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// What to do with images which aren't the same size as the block.  The image is never drawn outside
+// of the block.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ImageOverflow {
+    // Stretch the image to the block's size.
+    Stretch,
+    // Keep the image's natural size, aligned to the top left, and cut off anything that doesn't fit.
+    Clip,
+    // Scale the image to fit inside the block, keeping its aspect ratio, and center it.
+    Scale,
+    // Keep the image's natural size, centered, and cut off anything that doesn't fit.
+    Center,
+}
+
+impl Default for ImageOverflow {
+    fn default() -> Self { ImageOverflow::Stretch }
+}
+
+// What to show in place of the image when there isn't one (or it couldn't be loaded).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ImageFallback {
+    // Leave the space empty (`min_width` x `min_height`).
+    None,
+    // The initials of the app's name, e.g. "NM" for "network-manager".
+    Initials,
+    // Some text, e.g. an emoji.  Pango markup is allowed.
+    Glyph(String),
+}
+
+impl Default for ImageFallback {
+    fn default() -> Self { ImageFallback::None }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ImageBlockParameters {
     pub image_type: ImageType,
     // @NOTE: -1 to scale to size with aspect ratio kept?
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
     pub padding: Padding,
     pub rounding: f64,
+    #[serde(default)]
     pub scale_width: i32,
+    #[serde(default)]
     pub scale_height: i32,
     pub filter_mode: FilterMode,
 
+    // Size limits for the image, which is scaled to fit within them, keeping its aspect ratio.
+    // Negative maximums leave the image at its natural size.  When set, this is used instead of
+    // `scale_width`, `scale_height` and `overflow`.
+    pub dimensions: Option<Dimensions>,
+    // Keep the image's space when there's no image (or fallback), so the layout doesn't shift.
+    #[serde(default)]
+    pub render_when_empty: bool,
+    pub shadow: Option<Shadow>,
+
     #[serde(default)]
     pub min_width: i32,
     #[serde(default)]
     pub min_height: i32,
+    // How the image is composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+    #[serde(default)]
+    pub overflow: ImageOverflow,
+    // Text drawn in the middle of the block when there's no image.
+    #[serde(default)]
+    pub fallback: ImageFallback,
+    #[serde(default = "ImageBlockParameters::default_fallback_font")]
+    pub fallback_font: String,
+    #[serde(default = "ImageBlockParameters::default_fallback_color")]
+    pub fallback_color: Color,
 
     // The process of resizing the image and changing colorspace is relatively expensive,
     // so we should cache it.
     #[serde(skip)]
     cached_surface: Option<ImageSurface>,
+    // Each frame of an animated image and how long it's shown for in milliseconds, the frame being
+    // shown, and how long it's been shown for.  Empty for still images.
+    #[serde(skip)]
+    frames: Vec<(ImageSurface, u64)>,
+    #[serde(skip)]
+    frame_idx: usize,
+    #[serde(skip)]
+    frame_elapsed: u64,
+    // Position of the cached surface within the block.
+    #[serde(skip)]
+    image_offset: Vec2,
+    // Size of the block without padding.
+    #[serde(skip)]
+    real_size: Vec2,
+    // Resolved fallback text, empty if there's nothing to show.
+    #[serde(skip)]
+    fallback_text: String,
+}
+
+impl ImageBlockParameters {
+    fn default_fallback_font() -> String {
+        "Sans Bold 12".to_owned()
+    }
+
+    fn default_fallback_color() -> Color {
+        Color::from_rgba(1.0, 1.0, 1.0, 1.0)
+    }
+
+    // Size of the block without padding when there's no image to measure.
+    fn placeholder_size(&self, window: &NotifyWindow) -> Vec2 {
+        match &self.dimensions {
+            Some(dimensions) => {
+                let monitor = &window.monitor_rect;
+                let width = dimensions.width.resolve(monitor.width());
+                let height = dimensions.height.resolve(monitor.height());
+                Vec2::new(width.max.max(width.min) as f64, height.max.max(height.min) as f64)
+            },
+            None => Vec2::new(self.scale_width as f64, self.scale_height as f64),
+        }
+    }
+
+    fn padded_rect(&self) -> Rect {
+        Rect::new(
+            0.0, 0.0,
+            self.real_size.x + self.padding.width(),
+            self.real_size.y + self.padding.height(),
+        )
+    }
+
+    // Scale an image to fit the block, updating the block's size and where the image sits in it,
+    // and convert it for cairo.
+    fn create_surface(&mut self, img: &DynamicImage, window: &NotifyWindow) -> ImageSurface {
+        let filter_type = self.filter_mode.to_image_mode();
+        let img = match &self.dimensions {
+            Some(dimensions) => {
+                let monitor = &window.monitor_rect;
+                let width = dimensions.width.resolve(monitor.width());
+                let height = dimensions.height.resolve(monitor.height());
+                let max_width = if width.max < 0 { img.width() } else { width.max.max(1) as u32 };
+                let max_height = if height.max < 0 { img.height() } else { height.max.max(1) as u32 };
+
+                // `resize()` keeps the aspect ratio.  Images smaller than the minimum size are
+                // centered in the block.
+                let img = img.resize(max_width, max_height, filter_type);
+                let (img_width, img_height) = img.dimensions();
+                self.real_size = Vec2::new(
+                    (img_width as i32).max(width.min) as f64,
+                    (img_height as i32).max(height.min) as f64,
+                );
+                img
+            },
+            None => {
+                let (width, height) = (self.scale_width as u32, self.scale_height as u32);
+                match self.overflow {
+                    ImageOverflow::Stretch => img.resize_exact(width, height, filter_type),
+                    ImageOverflow::Scale => img.resize(width, height, filter_type),
+                    ImageOverflow::Clip | ImageOverflow::Center => img.clone(),
+                }
+            },
+        };
+        let (img_width, img_height) = img.dimensions();
+
+        let centered = self.dimensions.is_some() || match self.overflow {
+            ImageOverflow::Stretch | ImageOverflow::Clip => false,
+            ImageOverflow::Scale | ImageOverflow::Center => true,
+        };
+        self.image_offset = if centered {
+            Vec2::new(
+                (self.real_size.x - img_width as f64) / 2.0,
+                (self.real_size.y - img_height as f64) / 2.0,
+            )
+        } else {
+            Vec2::default()
+        };
+
+        let pixels = img
+            .to_bgra() // Cairo reads pixels back-to-front, so ARgb32 is actually BgrA32.
+            .into_raw();
+
+        let stride = cairo::Format::stride_for_width(Format::ARgb32, img_width)
+            .expect("Failed to calculate image stride.");
+
+        ImageSurface::create_for_data(pixels, Format::ARgb32, img_width as i32, img_height as i32, stride)
+            .expect("Failed to create image surface.")
+    }
+
+    fn resolve_fallback(&self, app_name: &str) -> String {
+        match &self.fallback {
+            ImageFallback::None => String::new(),
+            ImageFallback::Glyph(glyph) => glyph.clone(),
+            // Only letters and digits, so there's nothing to escape in the markup.
+            ImageFallback::Initials => app_name
+                .split(|c: char| c.is_whitespace() || c == '-' || c == '_' || c == '.')
+                .filter_map(|word| word.chars().next())
+                .filter(|c| c.is_alphanumeric())
+                .take(2)
+                .flat_map(char::to_uppercase)
+                .collect(),
+        }
+    }
 }
 
 impl DrawableLayoutElement for ImageBlockParameters {
     fn draw(&self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
-        window.context.set_operator(cairo::Operator::Over);
+        window.context.set_operator(self.operator.to_cairo());
+        let (width, height) = (self.real_size.x, self.real_size.y);
 
         // `cached_surface` should always exist on notifications with images, because we always
         // cache it.  If-let is just a precaution here.
-        if let Some(ref img_sfc) = self.cached_surface {
-            let mut rect = Rect::new(
-                0.0, 0.0,
-                self.scale_width as f64 + self.padding.width(),
-                self.scale_height as f64 + self.padding.height()
-            );
+        let surface = match self.frames.get(self.frame_idx) {
+            Some((frame_sfc, _)) => Some(frame_sfc),
+            None => self.cached_surface.as_ref(),
+        };
+        if let Some(img_sfc) = surface {
+            let mut rect = self.padded_rect();
             let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
             rect.set_xy(pos.x, pos.y);
 
             let (x, y) = (pos.x + self.padding.left, pos.y + self.padding.top);
-            window.context.set_source_surface(&img_sfc, x, y);
-            maths_utility::cairo_rounded_rectangle(
-                &window.context, x, y, self.scale_width as f64, self.scale_height as f64, self.rounding
-            );
-            //window.context.rectangle(x, y, self.scale_width as f64, self.scale_height as f64);
+            if let Some(shadow) = &self.shadow {
+                shadow.draw(&window.context, &Rect::new(x, y, width, height), self.rounding);
+            }
+
+            // Filling the block's shape (rather than the image's) keeps any overflow hidden.
+            window.context.set_source_surface(&img_sfc, x + self.image_offset.x, y + self.image_offset.y);
+            maths_utility::cairo_rounded_rectangle(&window.context, x, y, width, height, self.rounding);
+            //window.context.rectangle(x, y, width, height);
             window.context.fill();
-            maths_utility::debug_rect(&window.context, true, x, y, self.scale_width as f64, self.scale_height as f64);
+            maths_utility::debug_rect(&window.context, true, x, y, width, height);
+
+            rect
+        } else if !self.fallback_text.is_empty() {
+            let mut rect = self.padded_rect();
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+            rect.set_xy(pos.x, pos.y);
+
+            // Center the text in the space the image would have taken up.
+            window.text.set_text(&self.fallback_text, &self.fallback_font, -1, -1, &EllipsizeMode::NoEllipsize);
+            let text_rect = window.text.get_sized_rect(0, 0);
+            let text_pos = Vec2::new(
+                pos.x + self.padding.left + (width - text_rect.width()) / 2.0,
+                pos.y + self.padding.top + (height - text_rect.height()) / 2.0,
+            );
+            window.text.paint(&window.context, &text_pos, &self.fallback_color);
+            maths_utility::debug_rect(
+                &window.context, true,
+                pos.x + self.padding.left, pos.y + self.padding.top,
+                width, height,
+            );
+
+            rect
+        } else if self.render_when_empty {
+            let mut rect = self.padded_rect();
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+            rect.set_xy(pos.x, pos.y);
+            maths_utility::debug_rect(
+                &window.context, true,
+                pos.x + self.padding.left, pos.y + self.padding.top,
+                width, height,
+            );
 
             rect
         } else {
@@ -104,30 +320,40 @@ impl DrawableLayoutElement for ImageBlockParameters {
                     window.notification.hint_image.as_ref().or(window.notification.app_image.as_ref()),
             };
 
-        if let Some(img) = maybe_image {
-            let mut rect = Rect::new(
-                0.0, 0.0,
-                self.scale_width as f64 + self.padding.width(),
-                self.scale_height as f64 + self.padding.height(),
-            );
-
-            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
-
-            let filter_type = self.filter_mode.to_image_mode();
-            let pixels =
-                img.resize_exact(self.scale_width as u32, self.scale_height as u32, filter_type)
-                .to_bgra() // Cairo reads pixels back-to-front, so ARgb32 is actually BgrA32.
-                .into_raw();
+        self.fallback_text = match maybe_image {
+            Some(_) => String::new(),
+            None => self.resolve_fallback(&window.notification.app_name),
+        };
+        self.real_size = self.placeholder_size(window);
 
-            let stride = cairo::Format::stride_for_width(Format::ARgb32, self.scale_width as u32)
-                .expect("Failed to calculate image stride.");
-
-            let image_sfc =
-                ImageSurface::create_for_data(pixels, Format::ARgb32, self.scale_width, self.scale_height, stride)
-                    .expect("Failed to create image surface.");
+        // Only hint images can be animated.  The animation carries on from where it was if the
+        // layout is predicted again.
+        let animated = match (maybe_image, &window.notification.hint_image) {
+            (Some(img), Some(hint)) => std::ptr::eq(img, hint) && window.notification.hint_frames.len() > 1,
+            _ => false,
+        };
+        self.frames = if animated {
+            window.notification.hint_frames.iter()
+                .map(|(frame, delay)| (self.create_surface(frame, window), *delay))
+                .collect()
+        } else {
+            vec![]
+        };
+        if self.frame_idx >= self.frames.len() {
+            self.frame_idx = 0;
+            self.frame_elapsed = 0;
+        }
 
-            self.cached_surface = Some(image_sfc);
+        if let Some(img) = maybe_image {
+            self.cached_surface = Some(self.create_surface(img, window));
 
+            let mut rect = self.padded_rect();
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+            rect.set_xy(pos.x, pos.y);
+            rect
+        } else if !self.fallback_text.is_empty() || self.render_when_empty {
+            let mut rect = self.padded_rect();
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
             rect.set_xy(pos.x, pos.y);
             rect
         } else {
@@ -137,4 +363,21 @@ impl DrawableLayoutElement for ImageBlockParameters {
             rect
         }
     }
+
+    // Step through the frames of animated images.  Still images never need redrawing.
+    fn update(&mut self, delta_time: Duration, _window: &NotifyWindow) -> bool {
+        if self.frames.len() < 2 {
+            return false;
+        }
+
+        self.frame_elapsed += delta_time.as_millis() as u64;
+        let mut advanced = false;
+        while self.frame_elapsed >= self.frames[self.frame_idx].1 {
+            self.frame_elapsed -= self.frames[self.frame_idx].1;
+            self.frame_idx = (self.frame_idx + 1) % self.frames.len();
+            advanced = true;
+        }
+
+        advanced
+    }
 }
@@ -2,3 +2,6 @@ pub mod notification_block;
 pub mod text_block;
 pub mod scrolling_text_block;
 pub mod image_block;
+pub mod badge_block;
+pub mod progress_block;
+pub mod button_block;
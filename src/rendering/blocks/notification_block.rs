@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use serde::Deserialize;
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
 
 use crate::maths_utility::{self, Vec2, Rect};
 use crate::config::Color;
@@ -8,9 +8,74 @@ use crate::bus::dbus::Urgency;
 use crate::rendering::window::{NotifyWindow, UpdateModes};
 use crate::rendering::layout::{DrawableLayoutElement, Hook};
 
-#[derive(Debug, Deserialize, Clone)]
+// Which monitor notifications are shown on, written as a monitor index, `"primary"`, or `"cursor"`
+// (whichever monitor the mouse pointer is on when the notification arrives).
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorSelection {
+    Index(u32),
+    Primary,
+    Cursor,
+}
+
+impl Default for MonitorSelection {
+    fn default() -> Self { MonitorSelection::Primary }
+}
+
+impl Serialize for MonitorSelection {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            MonitorSelection::Index(idx) => serializer.serialize_u32(*idx),
+            MonitorSelection::Primary => serializer.serialize_str("primary"),
+            MonitorSelection::Cursor => serializer.serialize_str("cursor"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MonitorSelection {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MonitorVisitor;
+
+        impl<'de> de::Visitor<'de> for MonitorVisitor {
+            type Value = MonitorSelection;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a monitor index, \"primary\", or \"cursor\"")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<MonitorSelection, E> {
+                if v < 0 {
+                    return Err(E::custom(format!("invalid monitor index {}", v)));
+                }
+                Ok(MonitorSelection::Index(v as u32))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<MonitorSelection, E> {
+                Ok(MonitorSelection::Index(v as u32))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<MonitorSelection, E> {
+                match v.trim().to_lowercase().as_str() {
+                    "primary" => Ok(MonitorSelection::Primary),
+                    "cursor" => Ok(MonitorSelection::Cursor),
+                    _ => Err(E::custom(format!("invalid monitor \"{}\"", v))),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(MonitorVisitor)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NotificationBlockParameters {
-    pub monitor: u32,
+    #[serde(default)]
+    pub monitor: MonitorSelection,
     //pub monitor_hook: (AnchorPosition, AnchorPosition),
     //pub monitor_offset: Vec2,
 
@@ -25,11 +90,31 @@ pub struct NotificationBlockParameters {
     pub border_color_low: Option<Color>,
     pub border_color_critical: Option<Color>,
     pub border_color_paused: Option<Color>,
+    // Width divided by height.  Notifications are made wider or taller to keep to it, with their
+    // content centered, e.g. `1.0` for square notifications.
+    pub aspect_ratio: Option<f64>,
 
     #[serde(skip)]
     current_update_mode: UpdateModes,
 }
 
+impl NotificationBlockParameters {
+    // The size of a notification whose content is `width` x `height`, see `aspect_ratio`.  The
+    // notification only ever grows.
+    pub fn locked_size(&self, width: f64, height: f64) -> Vec2 {
+        match self.aspect_ratio {
+            Some(ratio) if ratio > 0.0 && height > 0.0 => {
+                if width / height < ratio {
+                    Vec2::new(height * ratio, height)
+                } else {
+                    Vec2::new(width, width / ratio)
+                }
+            },
+            _ => Vec2::new(width, height),
+        }
+    }
+}
+
 impl DrawableLayoutElement for NotificationBlockParameters {
     fn draw(&self, _hook: &Hook, _offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
         // Clear
@@ -39,11 +124,14 @@ impl DrawableLayoutElement for NotificationBlockParameters {
         window.context.set_operator(cairo::Operator::Source);
 
         // Draw border + background.
-        // If anything isn't updating, we count it as paused, which overrides urgency.
+        // If the fuse isn't burning, we count it as paused, which overrides urgency.
         // Otherwise, we evaluate urgency.
+        // Animations being paused (e.g. on battery) doesn't count.
         let bd_color = {
-            if window.update_mode != UpdateModes::all() {
+            if !window.update_mode.contains(UpdateModes::FUSE) {
                 self.border_color_paused.as_ref().unwrap_or(&self.border_color)
+            } else if let Some(color) = &window.rule_output.border_color {
+                color
             } else {
                 match window.notification.urgency {
                     Urgency::Low => self.border_color_low.as_ref().unwrap_or(&self.border_color),
@@ -57,7 +145,7 @@ impl DrawableLayoutElement for NotificationBlockParameters {
         window.context.set_source_rgba(bd_color.r, bd_color.g, bd_color.b, bd_color.a);
         window.context.paint();
 
-        let bg_color = &self.background_color;
+        let bg_color = window.rule_output.background_color.as_ref().unwrap_or(&self.background_color);
         let bw = &self.border_width;
         window.context.set_source_rgba(bg_color.r, bg_color.g, bg_color.b, bg_color.a);
         maths_utility::cairo_rounded_rectangle(
@@ -0,0 +1,119 @@
+use serde::{Serialize, Deserialize};
+
+use crate::maths_utility::{self, Vec2, Rect};
+use crate::config::{Padding, Color, Operator};
+use crate::rendering::{
+    window::NotifyWindow,
+    layout::{DrawableLayoutElement, LayoutBlock, Hook},
+    blocks::text_block::Dimensions,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProgressBlockParameters {
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
+    pub padding: Padding,
+    // The bar is as big as the maximum dimensions, or the minimum if the maximum is unlimited.
+    pub dimensions: Dimensions,
+    pub fill_color: Color,
+    pub background_color: Color,
+    // -- Optional fields.
+    // Width of the border around the bar, drawn in `fill_color`.
+    #[serde(default)]
+    pub border_width: f64,
+    // Draw an empty bar when there's no value, rather than hiding the block.
+    #[serde(default)]
+    pub render_when_empty: bool,
+    // Name of a numeric hint (0-100) to read the progress from, instead of the standard `value`
+    // hint.  Using different hints lets several bars show different values in one notification,
+    // e.g. "cpu" and "ram".
+    pub value_hint: Option<String>,
+    // How the bar is composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+    // Change the fill color depending on the value, see `Color::for_value()`.
+    #[serde(default)]
+    pub fill_color_thresholds: Vec<(i32, Color)>,
+
+    #[serde(skip)]
+    value: Option<i32>,
+    #[serde(skip)]
+    bar_size: Vec2,
+}
+
+impl ProgressBlockParameters {
+    // Whether the block takes up any space.
+    fn is_visible(&self) -> bool {
+        self.value.is_some() || self.render_when_empty
+    }
+
+    fn padded_rect(&self) -> Rect {
+        Rect::new(0.0, 0.0, self.bar_size.x + self.padding.width(), self.bar_size.y + self.padding.height())
+    }
+}
+
+impl DrawableLayoutElement for ProgressBlockParameters {
+    fn draw(&self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        // The bar is hidden if the notification doesn't have a value for it.
+        if !self.is_visible() {
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &Rect::EMPTY);
+            return Rect::new(pos.x, pos.y, 0.0, 0.0);
+        }
+
+        window.context.set_operator(self.operator.to_cairo());
+
+        let mut rect = self.padded_rect();
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        let (x, y) = (pos.x + self.padding.left, pos.y + self.padding.top);
+        let (width, height) = (self.bar_size.x, self.bar_size.y);
+
+        let bg = &self.background_color;
+        window.context.set_source_rgba(bg.r, bg.g, bg.b, bg.a);
+        window.context.rectangle(x, y, width, height);
+        window.context.fill();
+
+        let fill = Color::for_value(&self.fill_color, &self.fill_color_thresholds, self.value);
+        window.context.set_source_rgba(fill.r, fill.g, fill.b, fill.a);
+        if let Some(value) = self.value {
+            window.context.rectangle(x, y, width * value as f64 / 100.0, height);
+            window.context.fill();
+        }
+
+        // The border is drawn inside the bar, so it doesn't change the block's size.
+        if self.border_width > 0.0 {
+            let half = self.border_width / 2.0;
+            window.context.set_line_width(self.border_width);
+            window.context.rectangle(x + half, y + half, width - self.border_width, height - self.border_width);
+            window.context.stroke();
+        }
+
+        maths_utility::debug_rect(&window.context, true, x, y, width, height);
+
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+
+    fn predict_rect_and_init(&mut self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        let notification = &window.notification;
+        self.value = match &self.value_hint {
+            Some(hint) => notification.get_hint_i32(hint),
+            None => notification.value,
+        }.map(|v| v.max(0).min(100));
+
+        if !self.is_visible() {
+            let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &Rect::EMPTY);
+            return Rect::new(pos.x, pos.y, 0.0, 0.0);
+        }
+
+        let monitor = &window.monitor_rect;
+        let width = self.dimensions.width.resolve(monitor.width());
+        let height = self.dimensions.height.resolve(monitor.height());
+        let size = |min: i32, max: i32| (if max < 0 { min.max(0) } else { max.max(min) }) as f64;
+        self.bar_size = Vec2::new(size(width.min, width.max), size(height.min, height.max));
+
+        // Padded bounding rect, the same as text blocks, so anchoring works the same way.
+        let mut rect = self.padded_rect();
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+}
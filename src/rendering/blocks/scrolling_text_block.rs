@@ -1,22 +1,47 @@
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 
-use crate::maths_utility::{self, Rect, Vec2, MinMax};
-use crate::config::{Padding, Color};
+use crate::maths_utility::{self, Rect, Vec2, MinMax, Length};
+use crate::config::{Padding, Color, Operator};
 use crate::rendering::window::NotifyWindow;
-use crate::bus::dbus::Notification;
 use crate::rendering::layout::{LayoutBlock, DrawableLayoutElement, Hook};
 use crate::rendering::text::EllipsizeMode;
 use std::time::Duration;
 
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ScrollDirection {
+    // One line of text, scrolling sideways.
+    Horizontal,
+    // Text wrapped to `width`, scrolling up and down within `max_wrap_lines` lines (1 if unset).
+    Vertical,
+}
+
+impl Default for ScrollDirection {
+    fn default() -> Self { ScrollDirection::Horizontal }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ScrollMode {
+    // Scroll to the end of the text, then back again.
+    Bounce,
+    // Scroll continuously in one direction, with the start of the text following `marquee_gap`
+    // behind the end.  Only for horizontal scrolling.
+    Marquee,
+}
+
+impl Default for ScrollMode {
+    fn default() -> Self { ScrollMode::Bounce }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScrollingTextBlockParameters {
+    #[serde(deserialize_with = "Padding::deserialize_absolute")]
     pub padding: Padding,
     pub text: String,
     pub font: String,
     pub color: Color,
 
-    pub width: MinMax,
+    pub width: MinMax<Length>,
 
     pub scroll_speed: f64,
     pub lhs_dist: f64,
@@ -24,14 +49,37 @@ pub struct ScrollingTextBlockParameters {
     pub scroll_t: f64,
 
     // Optional fields ----
-    pub width_image_hint: Option<MinMax>,
-    pub width_image_app: Option<MinMax>,
-    pub width_image_both: Option<MinMax>,
+    pub width_image_hint: Option<MinMax<Length>>,
+    pub width_image_app: Option<MinMax<Length>>,
+    pub width_image_both: Option<MinMax<Length>>,
     #[serde(default)]
     pub render_when_empty: bool,
+    // How the text is composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+    // Corner radius of the area the text scrolls within, to match a rounded background.
+    #[serde(default)]
+    pub clip_rounding: f64,
+    // Wrap text that's too wide onto up to this many lines, instead of scrolling it sideways.  If
+    // it still doesn't fit, it scrolls up and down instead.
+    pub max_wrap_lines: Option<u32>,
+    #[serde(default)]
+    pub direction: ScrollDirection,
+    #[serde(default)]
+    pub scroll_mode: ScrollMode,
+    // Space between the end of the text and its start, in `Marquee` mode.
+    #[serde(default = "ScrollingTextBlockParameters::default_marquee_gap")]
+    pub marquee_gap: f64,
+    // Squash runs of spaces and blank lines, which some apps send a lot of, see
+    // `maths_utility::collapse_whitespace()`.
+    #[serde(default)]
+    pub collapse_whitespace: bool,
 
     #[serde(skip)]
     real_text: String,
+    // Whether the text is wrapped (see `max_wrap_lines`) rather than on one line.
+    #[serde(skip)]
+    wrapped: bool,
 
     #[serde(skip)]
     clip_rect: Rect,
@@ -44,16 +92,132 @@ pub struct ScrollingTextBlockParameters {
 
     #[serde(skip)]
     update_enabled: bool,
+    // Where the text has scrolled to and how fast it's moving, which start at `scroll_t` and
+    // `scroll_speed` each time the block is predicted.
+    #[serde(skip)]
+    current_t: f64,
+    #[serde(skip)]
+    current_speed: f64,
 }
 
 impl ScrollingTextBlockParameters {
-    fn get_width(&self, notification: &Notification) -> &MinMax {
-        match (notification.app_image.is_some(), notification.hint_image.is_some()) {
+    fn default_marquee_gap() -> f64 {
+        40.0
+    }
+
+    // Forget everything from the last prediction and any scrolling since, so a prediction never
+    // depends on the one before it.
+    fn reset_state(&mut self) {
+        self.real_text = String::new();
+        self.wrapped = false;
+        self.clip_rect = Rect::EMPTY;
+        self.text_rect = Rect::EMPTY;
+        self.scroll_distance = 0.0;
+        self.real_width = MinMax::default();
+        self.update_enabled = false;
+        self.current_t = self.scroll_t;
+        self.current_speed = self.scroll_speed;
+    }
+
+    // Whether the text scrolls continuously, see `ScrollMode::Marquee`.
+    fn is_marquee(&self) -> bool {
+        match self.scroll_mode {
+            ScrollMode::Marquee => !self.wrapped,
+            ScrollMode::Bounce => false,
+        }
+    }
+
+    // How far the text moves before a marquee repeats.
+    fn marquee_cycle(&self) -> f64 {
+        self.text_rect.width() + self.marquee_gap
+    }
+
+    // Width limits in pixels, resolved against the window's monitor.
+    fn get_width(&self, window: &NotifyWindow) -> MinMax {
+        let notification = &window.notification;
+        let width = match (notification.app_image.is_some(), notification.hint_image.is_some()) {
             (true, true) => self.width_image_both.as_ref().unwrap_or(&self.width),
             (true, false) => self.width_image_app.as_ref().unwrap_or(&self.width),
             (false, true) => self.width_image_hint.as_ref().unwrap_or(&self.width),
             (false, false) => &self.width,
+        };
+
+        width.resolve(window.monitor_rect.width())
+    }
+
+    // The block's text with the notification's details filled in.
+    fn format_text(&self, window: &NotifyWindow) -> String {
+        let text = maths_utility::format_notification_string(&self.text, window);
+        if self.collapse_whitespace {
+            return maths_utility::collapse_whitespace(&text);
         }
+
+        text
+    }
+
+    // Wrap the text to `real_width`, showing up to `lines` lines at a time.  The text only scrolls
+    // (vertically) if there are more lines than that.
+    fn predict_wrapped(
+        &mut self,
+        text: &str,
+        lines: u32,
+        hook: &Hook,
+        offset: &Vec2,
+        parent_rect: &Rect,
+        window: &NotifyWindow,
+    ) -> Rect {
+        // `text_rect` -- Every line of the wrapped text.
+        // `clip_rect` -- The first `lines` lines, which is all that's shown at once.
+        window.text.set_text(text, window.font(&self.font), self.real_width.max, -1, &EllipsizeMode::NoEllipsize);
+        let text_rect = window.text.get_sized_rect(self.real_width.min, 0);
+        window.text.set_max_lines(lines);
+        let clip_rect = window.text.get_sized_rect(self.real_width.min, 0);
+
+        self.wrapped = true;
+        self.update_enabled = text_rect.height() > clip_rect.height();
+        self.scroll_distance = text_rect.height() - clip_rect.height();
+        self.real_text = text.to_owned();
+
+        let mut rect = Rect::new(
+            0.0, 0.0,
+            clip_rect.width() + self.padding.width(),
+            clip_rect.height() + self.padding.height(),
+        );
+        self.text_rect = text_rect;
+        self.clip_rect = clip_rect;
+
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        rect
+    }
+
+    fn draw_wrapped(&self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
+        let mut rect = Rect::new(
+            0.0, 0.0,
+            self.clip_rect.width() + self.padding.width(),
+            self.clip_rect.height() + self.padding.height(),
+        );
+        let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+
+        let (x, y) = (pos.x + self.padding.left, pos.y + self.padding.top);
+        maths_utility::debug_rect(&window.context, true, x, y, self.clip_rect.width(), self.clip_rect.height());
+
+        window.text.set_text(&self.real_text, window.font(&self.font), self.real_width.max, -1, &EllipsizeMode::NoEllipsize);
+        if self.update_enabled {
+            maths_utility::cairo_rounded_rectangle(
+                &window.context, x, y, self.clip_rect.width(), self.clip_rect.height(), self.clip_rounding,
+            );
+            window.context.clip();
+
+            // Bounce between showing the first and last lines.
+            let text_y = maths_utility::lerp(y - self.scroll_distance, y, self.current_t);
+            window.text.paint(&window.context, &Vec2::new(x, text_y), &self.color);
+        } else {
+            window.text.paint(&window.context, &Vec2::new(x, y), &self.color);
+        }
+
+        rect
     }
 }
 
@@ -66,6 +230,12 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
             return Rect::new(pos.x, pos.y, 0.0, 0.0);
         }
 
+        window.context.set_operator(self.operator.to_cairo());
+
+        if self.wrapped {
+            return self.draw_wrapped(hook, offset, parent_rect, window);
+        }
+
         let width = &self.real_width;
 
         // First, generate bounding rect with padding and stuff -- the space the text will
@@ -73,11 +243,11 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
         // We could cache this rect, but haven't yet.
         // We need to set some ellipsize mode, or the text size will be forced larger despite our
         // max width/height.
-        window.text.set_text(&self.real_text, &self.font, width.max, 0, &EllipsizeMode::Middle);
+        window.text.set_text(&self.real_text, window.font(&self.font), width.max, 0, &EllipsizeMode::Middle);
         let mut rect = window.text.get_sized_padded_rect(&self.padding, width.min, 0);
 
         // Set the text to the real (scrolling) string.
-        window.text.set_text(&self.real_text, &self.font, -1, 0, &EllipsizeMode::NoEllipsize);
+        window.text.set_text(&self.real_text, window.font(&self.font), -1, 0, &EllipsizeMode::NoEllipsize);
 
         let mut pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
         pos.x += self.padding.left;
@@ -88,15 +258,31 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
         // If we're larger than the max size, then we should scroll, which is just changing the
         // text's x position really.
         if self.text_rect.width() > width.max as f64 {
-            window.context.rectangle(
+            maths_utility::cairo_rounded_rectangle(
+                &window.context,
                 pos.x,
                 pos.y,
                 self.clip_rect.width(),
-                self.clip_rect.height()
+                self.clip_rect.height(),
+                self.clip_rounding,
             );
             window.context.clip();
 
-            // @TODO: also add dynamic scroll option.
+            // Draw the text a second time behind the first, so the marquee never runs out of text.
+            if self.is_marquee() {
+                let temp = pos.x;
+                pos.x = temp + self.lhs_dist - self.current_t * self.marquee_cycle();
+                window.text.paint(&window.context, &pos, &self.color);
+                pos.x += self.marquee_cycle();
+                window.text.paint(&window.context, &pos, &self.color);
+                pos.x = temp;
+
+                pos.x -= self.padding.left;
+                pos.y -= self.padding.top;
+                rect.set_xy(pos.x, pos.y);
+                return rect;
+            }
+
             // Equivalent to clip_rect.left() + self.lhs_dist if clip_rect had correct coordinates.
             let bounce_left = pos.x + self.padding.left + self.lhs_dist;
             // Equivalent to clip_rect.right() - self.rhs_dist - text_rect.width() if clip_rect had
@@ -104,7 +290,7 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
             let bounce_right =
                 pos.x + self.padding.left + self.clip_rect.width() - self.rhs_dist - self.text_rect.width();
 
-            let lerp = maths_utility::lerp(bounce_right, bounce_left, self.scroll_t);
+            let lerp = maths_utility::lerp(bounce_right, bounce_left, self.current_t);
             // Keep track of pos.x; it's important for the layout.
             let temp = pos.x;
             pos.x = lerp;
@@ -122,10 +308,10 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
     }
 
     fn predict_rect_and_init(&mut self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
-        let text = maths_utility::format_notification_string(&self.text, &window.notification);
+        self.reset_state();
+        let text = self.format_text(window);
 
         if text.is_empty() && !self.render_when_empty {
-            self.update_enabled = false;
             self.real_text = text;
             let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &Rect::EMPTY);
             return Rect::new(pos.x, pos.y, 0.0, 0.0);
@@ -133,26 +319,34 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
 
         // We cache real_width because we need to access it in `update()` later, which doesn't have
         // access to the notification.
-        self.real_width = self.get_width(&window.notification).clone();
+        self.real_width = self.get_width(window);
 
         // Max height of 0 = one line of text.
-        window.text.set_text(&text, &self.font, self.real_width.max, 0, &EllipsizeMode::Middle);
+        window.text.set_text(&text, window.font(&self.font), self.real_width.max, 0, &EllipsizeMode::Middle);
 
         // `rect`      -- Padded rect, for calculating bounding box.
         // `clip_rect` -- Unpadded rect, used for clipping.
         // `text_rect` -- Real text rect, with infinite length.
         let mut rect = window.text.get_sized_padded_rect(&self.padding, self.real_width.min, 0);
         let clip_rect = window.text.get_sized_padded_rect(&Padding::new(0.0, 0.0, 0.0, 0.0), 0, 0);
-        window.text.set_text(&text, &self.font, -1, 0, &EllipsizeMode::NoEllipsize);
+        window.text.set_text(&text, window.font(&self.font), -1, 0, &EllipsizeMode::NoEllipsize);
         let text_rect = window.text.get_sized_padded_rect(&self.padding, 0, 0);
 
-        if text_rect.width() > self.real_width.max as f64 {
-            self.update_enabled = true;
+        // Text which is too wide can be wrapped instead, see `max_wrap_lines`.  Vertical scrolling
+        // is always wrapped.
+        let wrap_lines = match self.direction {
+            ScrollDirection::Vertical => Some(self.max_wrap_lines.unwrap_or(1)),
+            ScrollDirection::Horizontal if text_rect.width() > self.real_width.max as f64 => self.max_wrap_lines,
+            ScrollDirection::Horizontal => None,
+        };
+        if let Some(lines) = wrap_lines {
+            return self.predict_wrapped(&text, lines, hook, offset, parent_rect, window);
         }
 
+        self.update_enabled = text_rect.width() > self.real_width.max as f64;
+
         let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
 
-        // @TODO: also add dynamic scroll option.
         // `bounce_left`  -- Equivalent to clip_rect.left() + self.lhs_dist if clip_rect had correct coordinates.
         // `bounce_right` -- Equivalent to clip_rect.right() - self.rhs_dist - text_rect.width() if clip_rect had
         // correct coordinates.
@@ -168,27 +362,52 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
         rect
     }
 
-    fn update(&mut self, delta_time: Duration, _window: &NotifyWindow) -> bool {
-        if !self.update_enabled {
-            return false;
+    fn update(&mut self, delta_time: Duration, window: &NotifyWindow) -> bool {
+        // Keep text with time tokens (e.g. `%{remaining}`) up to date.
+        let mut text_changed = false;
+        if maths_utility::is_time_dependent(&self.text) {
+            let text = self.format_text(window);
+            if text != self.real_text {
+                self.real_text = text;
+                text_changed = true;
+            }
+        }
+
+        // Scrolling holds still while animations are paused, see `Config::pause_animations_on_battery`.
+        if !self.update_enabled || window.animations_paused {
+            return text_changed;
         }
 
-        let width = &self.real_width;
+        // Wrapped text scrolls up and down, through the height of the clip area rather than its
+        // width.
+        let extent = if self.wrapped {
+            self.clip_rect.height()
+        } else {
+            self.real_width.max as f64
+        };
+
+        // A marquee goes round and round, at the same speed as it would bounce.
+        if self.is_marquee() {
+            let cycle = self.marquee_cycle();
+            self.current_t += delta_time.as_secs_f64() * self.current_speed * (extent / cycle);
+            self.current_t = self.current_t.rem_euclid(1.0);
+            return true;
+        }
 
-        // Increase proportionally to distance (text width).
-        self.scroll_t +=
-            delta_time.as_secs_f64() * self.scroll_speed * (width.max as f64 / self.scroll_distance);
+        // Increase proportionally to distance (text width or height).
+        self.current_t +=
+            delta_time.as_secs_f64() * self.current_speed * (extent / self.scroll_distance);
 
         // If scrolling right.
-        if self.scroll_speed > 0.0 {
+        if self.current_speed > 0.0 {
             // If reached right edge, reverse.
-            if self.scroll_t >= 1.0 {
-                self.scroll_speed = -self.scroll_speed;
+            if self.current_t >= 1.0 {
+                self.current_speed = -self.current_speed;
             }
-        } else if self.scroll_speed < 0.0 {
+        } else if self.current_speed < 0.0 {
             // If reached left edge, reverse.
-            if self.scroll_t <= 0.0 {
-                self.scroll_speed = -self.scroll_speed;
+            if self.current_t <= 0.0 {
+                self.current_speed = -self.current_speed;
             }
         }
 
@@ -196,3 +415,49 @@ impl DrawableLayoutElement for ScrollingTextBlockParameters {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::bus::dbus::Notification;
+    use crate::config::{AnchorPosition, Config};
+    use crate::rules::RuleOutput;
+
+    #[test]
+    fn prediction_resets_scrolling() {
+        Config::init_for_tests();
+        let body = "A body which is far too long to fit in the block, so it has to scroll";
+        let notification = Notification::from_dbus(1, "app", 0, "", "Summary", body, vec![], HashMap::new(), -1);
+        let window = NotifyWindow::new_offscreen(notification, RuleOutput::default());
+
+        let mut block: ScrollingTextBlockParameters = ron::de::from_str(r##"(
+            padding: Padding(left: 0.0, right: 0.0, top: 0.0, bottom: 0.0),
+            text: "%b",
+            font: "Arial 11",
+            color: Color(hex: "#ffffff"),
+            width: (min: 0, max: 50),
+            scroll_speed: 0.1,
+            lhs_dist: 0.0,
+            rhs_dist: 0.0,
+            scroll_t: 1.0,
+        )"##).unwrap();
+        let hook = Hook { parent_anchor: AnchorPosition::TL, self_anchor: AnchorPosition::TL, vertical_align: None };
+        let parent_rect = Rect::new(0.0, 0.0, 500.0, 500.0);
+
+        let first = block.predict_rect_and_init(&hook, &Vec2::default(), &parent_rect, &window);
+        assert!(block.update_enabled);
+        for _ in 0..25 {
+            block.update(Duration::from_millis(100), &window);
+        }
+        assert!(block.current_t < 1.0);
+        assert!(block.current_speed < 0.0);
+
+        // Predicting again (e.g. when the window is hovered) starts scrolling over, like a new
+        // copy of the layout would.
+        let second = block.predict_rect_and_init(&hook, &Vec2::default(), &parent_rect, &window);
+        assert_eq!(first, second);
+        assert_eq!(block.current_t, 1.0);
+        assert_eq!(block.current_speed, 0.1);
+    }
+}
@@ -1,22 +1,35 @@
-use serde::Deserialize;
+use std::time::Duration;
 
-use crate::maths_utility::{Vec2, Rect, MinMax};
-use crate::config::{Config, Padding, Color};
-use crate::bus::dbus::Notification;
+use serde::{Serialize, Deserialize};
+
+use crate::maths_utility::{Vec2, Rect, MinMax, Length};
+use crate::config::{Config, Padding, Color, Operator, Shadow};
 use crate::rendering::{
     window::NotifyWindow,
     layout::{DrawableLayoutElement, LayoutBlock, Hook},
-    text::EllipsizeMode,
+    text::{self, EllipsizeMode, TextAlignment},
 };
 use crate::maths_utility;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Dimensions {
-    width: MinMax,
-    height: MinMax,
+    pub width: MinMax<Length>,
+    pub height: MinMax<Length>,
+}
+
+// How text is revealed when the notification first appears.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Reveal {
+    None,
+    // Reveal the text word by word over the given number of milliseconds.
+    Typewriter(u64),
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Default for Reveal {
+    fn default() -> Self { Reveal::None }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TextBlockParameters {
     pub padding: Padding,
     //https://developer.gnome.org/pango/stable/pango-Markup.html
@@ -32,18 +45,113 @@ pub struct TextBlockParameters {
     pub ellipsize: EllipsizeMode,
     #[serde(default)]
     pub render_when_empty: bool,
+    // Wrap long words (URLs, hashes, etc) at any character instead of letting them overflow.
+    #[serde(default)]
+    pub break_anywhere: bool,
+    // How the lines of multi-line text line up.  This doesn't change the size of the block.
+    #[serde(default)]
+    pub alignment: TextAlignment,
+    // How the text is composited onto what's already been drawn.
+    #[serde(default)]
+    pub operator: Operator,
+    #[serde(default)]
+    pub reveal: Reveal,
+    // Only show this many lines until the notification is hovered, at which point it expands to
+    // show all of the text.
+    pub preview_lines: Option<u32>,
+    // OpenType features to render the text with, e.g. `["tnum"]` to line up columns of digits.
+    #[serde(default)]
+    pub font_features: Vec<String>,
+    // Change the text color depending on the notification's `value` hint, see `Color::for_value()`.
+    #[serde(default)]
+    pub color_thresholds: Vec<(i32, Color)>,
+    // Squash runs of spaces and blank lines, which some apps send a lot of, see
+    // `maths_utility::collapse_whitespace()`.
+    #[serde(default)]
+    pub collapse_whitespace: bool,
+    // Fill and outline the block (including its padding), for card-style layouts.  The border is
+    // drawn inside the block.
+    pub background_color: Option<Color>,
+    pub border_color: Option<Color>,
+    #[serde(default)]
+    pub border_width: f64,
+    pub shadow: Option<Shadow>,
 
     #[serde(skip)]
     real_text: String,
+    // Time since the block was created, used to progress the reveal.
+    #[serde(skip)]
+    reveal_elapsed: u64,
 }
 
 impl TextBlockParameters {
-    fn get_dimensions(&self, notification: &Notification) -> &Dimensions {
-        match (notification.app_image.is_some(), notification.hint_image.is_some()) {
+    // Width and height limits in pixels, resolved against the window's monitor.
+    fn get_dimensions(&self, window: &NotifyWindow) -> (MinMax, MinMax) {
+        let notification = &window.notification;
+        let dimensions = match (notification.app_image.is_some(), notification.hint_image.is_some()) {
             (true, true) => self.dimensions_image_both.as_ref().unwrap_or(&self.dimensions),
             (true, false) => self.dimensions_image_app.as_ref().unwrap_or(&self.dimensions),
             (false, true) => self.dimensions_image_hint.as_ref().unwrap_or(&self.dimensions),
             (false, false) => &self.dimensions,
+        };
+
+        let monitor = &window.monitor_rect;
+        (dimensions.width.resolve(monitor.width()), dimensions.height.resolve(monitor.height()))
+    }
+
+    // The block's text with the notification's details filled in.
+    fn format_text(&self, window: &NotifyWindow) -> String {
+        let text = maths_utility::format_notification_string(&self.text, window);
+        if self.collapse_whitespace {
+            return maths_utility::collapse_whitespace(&text);
+        }
+
+        text
+    }
+
+    fn draw_background(&self, rect: &Rect, window: &NotifyWindow) {
+        if let Some(shadow) = &self.shadow {
+            shadow.draw(&window.context, rect, 0.0);
+        }
+
+        if let Some(bg) = &self.background_color {
+            window.context.set_source_rgba(bg.r, bg.g, bg.b, bg.a);
+            window.context.rectangle(rect.x(), rect.y(), rect.width(), rect.height());
+            window.context.fill();
+        }
+
+        if let Some(bc) = &self.border_color {
+            if self.border_width > 0.0 {
+                // Strokes are centered on the path, so inset it by half the width.
+                let bw = self.border_width;
+                window.context.set_source_rgba(bc.r, bc.g, bc.b, bc.a);
+                window.context.set_line_width(bw);
+                window.context.rectangle(
+                    rect.x() + bw / 2.0, rect.y() + bw / 2.0,
+                    (rect.width() - bw).max(0.0), (rect.height() - bw).max(0.0),
+                );
+                window.context.stroke();
+            }
+        }
+    }
+
+    // Options which need to be set on the text renderer after `set_text()`.
+    fn apply_text_options(&self, window: &NotifyWindow) {
+        window.text.set_break_anywhere(self.break_anywhere);
+        window.text.set_alignment(&self.alignment);
+        if let Some(lines) = self.preview_lines {
+            if !window.expanded {
+                window.text.set_max_lines(lines);
+            }
+        }
+    }
+
+    // How much of the text should be visible, from 0.0 to 1.0.
+    fn reveal_fraction(&self) -> f64 {
+        match self.reveal {
+            Reveal::None => 1.0,
+            Reveal::Typewriter(0) => 1.0,
+            Reveal::Typewriter(duration) => (self.reveal_elapsed as f64 / duration as f64).min(1.0),
         }
     }
 }
@@ -57,31 +165,45 @@ impl DrawableLayoutElement for TextBlockParameters {
             return Rect::new(pos.x, pos.y, 0.0, 0.0);
         }
 
-        window.context.set_operator(cairo::Operator::Over);
+        window.context.set_operator(self.operator.to_cairo());
 
-        let dimensions = self.get_dimensions(&window.notification);
+        let (width, height) = self.get_dimensions(window);
 
+        let markup = text::with_font_features(&self.real_text, &self.font_features);
         window.text
-            .set_text(&self.real_text, &self.font, dimensions.width.max, dimensions.height.max, &self.ellipsize);
+            .set_text(&markup, window.font(&self.font), width.max, height.max, &self.ellipsize);
+        self.apply_text_options(window);
+        let padding = self.padding.resolve(&window.text.get_sized_rect(width.min, height.min));
         let mut rect =
-            window.text.get_sized_padded_rect(&self.padding, dimensions.width.min, dimensions.height.min);
+            window.text.get_sized_padded_rect(&padding, width.min, height.min);
 
         let pos = LayoutBlock::find_anchor_pos(hook, offset, parent_rect, &rect);
+        rect.set_xy(pos.x, pos.y);
+        self.draw_background(&rect, window);
+
+        let color = Color::for_value(&self.color, &self.color_thresholds, window.notification.value);
 
         // Move block to text position (ignoring padding) for draw operation.
-        window.text.paint_padded(&window.context, &pos, &self.color, &self.padding);
+        let fraction = self.reveal_fraction();
+        if fraction < 1.0 {
+            window.context.save();
+            window.text.clip_revealed_words(&window.context, &pos, &padding, fraction);
+            window.text.paint_padded(&window.context, &pos, color, &padding);
+            window.context.restore();
+        } else {
+            window.text.paint_padded(&window.context, &pos, color, &padding);
+        }
         // Debug, unpadded drawing, to help users.
         if Config::get().debug {
-            let r = window.text.get_sized_rect(dimensions.width.min, dimensions.height.min);
-            maths_utility::debug_rect(&window.context, true, pos.x + self.padding.left, pos.y + self.padding.top, r.width(), r.height());
+            let r = window.text.get_sized_rect(width.min, height.min);
+            maths_utility::debug_rect(&window.context, true, pos.x + padding.left, pos.y + padding.top, r.width(), r.height());
         }
 
-        rect.set_xy(pos.x, pos.y);
         rect
     }
 
     fn predict_rect_and_init(&mut self, hook: &Hook, offset: &Vec2, parent_rect: &Rect, window: &NotifyWindow) -> Rect {
-        let text = maths_utility::format_notification_string(&self.text, &window.notification);
+        let text = self.format_text(window);
 
         // If text is empty and we shouldn't render it, then we should be safe to just return an
         // empty rect.
@@ -93,9 +215,12 @@ impl DrawableLayoutElement for TextBlockParameters {
             return Rect::new(pos.x, pos.y, 0.0, 0.0);
         }
 
-        let dimensions = self.get_dimensions(&window.notification);
-        window.text.set_text(&text, &self.font, dimensions.width.max, dimensions.height.max, &self.ellipsize);
-        let mut rect = window.text.get_sized_padded_rect(&self.padding, dimensions.width.min, dimensions.height.min);
+        let (width, height) = self.get_dimensions(window);
+        let markup = text::with_font_features(&text, &self.font_features);
+        window.text.set_text(&markup, window.font(&self.font), width.max, height.max, &self.ellipsize);
+        self.apply_text_options(window);
+        let padding = self.padding.resolve(&window.text.get_sized_rect(width.min, height.min));
+        let mut rect = window.text.get_sized_padded_rect(&padding, width.min, height.min);
 
         self.real_text = text;
 
@@ -103,4 +228,26 @@ impl DrawableLayoutElement for TextBlockParameters {
         rect.set_xy(pos.x, pos.y);
         rect
     }
+
+    fn update(&mut self, delta_time: Duration, window: &NotifyWindow) -> bool {
+        let mut dirty = false;
+
+        // Keep redrawing until the text is fully revealed.
+        if self.reveal_fraction() < 1.0 {
+            self.reveal_elapsed += delta_time.as_millis() as u64;
+            dirty = true;
+        }
+
+        // Text with time tokens (e.g. `%{remaining}`) needs to be kept up to date, but we only need
+        // to redraw when it actually changes.
+        if maths_utility::is_time_dependent(&self.text) {
+            let text = self.format_text(window);
+            if text != self.real_text {
+                self.real_text = text;
+                dirty = true;
+            }
+        }
+
+        dirty
+    }
 }
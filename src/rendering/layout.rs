@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 
 use crate::{
     rendering::blocks::{
@@ -8,28 +8,87 @@ use crate::{
         text_block::TextBlockParameters,
         scrolling_text_block::ScrollingTextBlockParameters,
         image_block::ImageBlockParameters,
+        badge_block::BadgeBlockParameters,
+        progress_block::ProgressBlockParameters,
+        button_block::ButtonBlockParameters,
     },
     maths_utility::{Vec2, Rect},
-    config::{Config, AnchorPosition},
+    config::{Config, AnchorPosition, Offset},
     rendering::window::NotifyWindow,
     wired_derive::DrawableLayoutElement,
 };
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LayoutBlock {
     pub name: String,
     pub parent: String,
     pub hook: Hook,
-    pub offset: Vec2,
+    // Pixels, or a percentage of the parent block's size (or the monitor's, for the root block).
+    pub offset: Offset,
     pub params: LayoutElement,
-    #[serde(skip)]
+    // Overlay blocks are drawn as normal, but don't count towards the size of the notification,
+    // so things like badges or watermarks can sit on top of other blocks without growing the window.
+    // Anything sticking out past the rest of the notification will be cut off.
+    #[serde(default)]
+    pub overlay: bool,
+    // Group header blocks (and their children) are only shown on the first notification of a group
+    // of notifications from the same app, see `Config::stack_by_app`.
+    #[serde(default)]
+    pub group_header: bool,
+    // Cut off anything the block draws outside of the space it was given when the notification was
+    // laid out, so it can't draw over its siblings.  Children aren't affected.
+    #[serde(default)]
+    pub clip_to_rect: bool,
+    // Only show the block (and its children) while the notification is read, or unread, e.g. to
+    // accent new notifications.  Notifications become read when they're first hovered.
+    pub read_state: Option<ReadState>,
+    // Built from the `parent` of each block when the config is loaded.
+    #[serde(skip_deserializing)]
     pub children: Vec<LayoutBlock>,
+    // Result of the last `predict_rect_and_init`, for `clip_to_rect`.
+    #[serde(skip)]
+    predicted_rect: Rect,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ReadState {
+    Read,
+    Unread,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Hook {
     pub parent_anchor: AnchorPosition,
     pub self_anchor: AnchorPosition,
+    // Optionally overrides the vertical position given by the anchors, aligning this block
+    // against the parent's rect instead.  Useful for lining text up against an image.
+    pub vertical_align: Option<VerticalAlign>,
+}
+
+impl Hook {
+    // The hook for the other side (horizontally and/or vertically) of the parent.
+    pub fn flipped(&self, horizontal: bool, vertical: bool) -> Hook {
+        let flip = |anchor: &AnchorPosition| {
+            let anchor = if horizontal { anchor.mirrored() } else { anchor.clone() };
+            if vertical { anchor.flipped() } else { anchor }
+        };
+
+        Hook {
+            parent_anchor: flip(&self.parent_anchor),
+            self_anchor: flip(&self.self_anchor),
+            vertical_align: self.vertical_align.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum VerticalAlign {
+    Top,
+    Center,
+    // Align this block's text baseline with the parent's.  Blocks without text use their bottom
+    // edge as their baseline.
+    Baseline,
+    Bottom,
 }
 
 
@@ -38,12 +97,15 @@ pub struct Hook {
 // based on the result of `predict_rect_and_init` to make sure we don't draw off canvas, so
 // the result of `LayoutBlock::find_anchor_pos()` can change between `predict_rect_and_init` and
 // `draw`!
-#[derive(Debug, Deserialize, Clone, DrawableLayoutElement)]
+#[derive(Debug, Serialize, Deserialize, Clone, DrawableLayoutElement)]
 pub enum LayoutElement {
     NotificationBlock(NotificationBlockParameters),
     TextBlock(TextBlockParameters),
     ScrollingTextBlock(ScrollingTextBlockParameters),
     ImageBlock(ImageBlockParameters),
+    BadgeBlock(BadgeBlockParameters),
+    ProgressBlock(ProgressBlockParameters),
+    ButtonBlock(ButtonBlockParameters),
 }
 
 impl LayoutBlock {
@@ -57,6 +119,15 @@ impl LayoutBlock {
         anchor.x -= self_anchor.x;
         anchor.y -= self_anchor.y;
 
+        if let Some(align) = &hook.vertical_align {
+            anchor.y = match align {
+                VerticalAlign::Top => parent_rect.top(),
+                VerticalAlign::Center => parent_rect.top() + (parent_rect.height() - self_rect.height()) / 2.0,
+                VerticalAlign::Baseline => parent_rect.top() + parent_rect.baseline() - self_rect.baseline(),
+                VerticalAlign::Bottom => parent_rect.bottom() - self_rect.height(),
+            };
+        }
+
         // The `offset` config option is just applied on top.
         anchor.x += offset.x;
         anchor.y += offset.y;
@@ -64,10 +135,40 @@ impl LayoutBlock {
         anchor
     }
 
+    fn is_hidden(&self, window: &NotifyWindow) -> bool {
+        let wrong_read_state = match &self.read_state {
+            Some(ReadState::Read) => !window.read,
+            Some(ReadState::Unread) => window.read,
+            None => false,
+        };
+
+        wrong_read_state || (
+            self.group_header &&
+                !(Config::get().stack_by_app && window.group.first && window.group.count > 1)
+        )
+    }
+
     // Call draw on each block in tree.
     pub fn draw_tree(&self, window: &NotifyWindow, parent_rect: &Rect, accum_rect: Rect) -> Rect {
-        let rect = self.params.draw(&self.hook, &self.offset, parent_rect, window);
-        let mut acc_rect = accum_rect.union(&rect);
+        if self.is_hidden(window) {
+            return accum_rect;
+        }
+
+        let offset = self.offset.resolve(parent_rect);
+        let rect = if self.clip_to_rect {
+            // The block isn't necessarily drawn where it was predicted (see `LayoutElement`), so
+            // find where the predicted rect would be now.
+            let pos = LayoutBlock::find_anchor_pos(&self.hook, &offset, parent_rect, &self.predicted_rect);
+            window.context.save();
+            window.context.rectangle(pos.x, pos.y, self.predicted_rect.width(), self.predicted_rect.height());
+            window.context.clip();
+            let rect = self.params.draw(&self.hook, &offset, parent_rect, window);
+            window.context.restore();
+            rect
+        } else {
+            self.params.draw(&self.hook, &offset, parent_rect, window)
+        };
+        let mut acc_rect = if self.overlay { accum_rect } else { accum_rect.union(&rect) };
 
         // Draw debug rect around bounding box.
         if Config::get().debug {
@@ -87,13 +188,19 @@ impl LayoutBlock {
 
     // Predict the size of an entire layout, and initialize elements.
     pub fn predict_rect_tree_and_init(&mut self, window: &NotifyWindow, parent_rect: &Rect, accum_rect: Rect) -> Rect {
+        if self.is_hidden(window) {
+            return accum_rect;
+        }
+
         // Predict size is supposed to be relatively cheap and lets us predict the size of elements,
         // so we can set window size and other stuff ahead of time.  We also initialize some stuff in
         // here to save performance.
         // `predict_rect_and_init` finds the bounding box of an individual element -- children are not
         // involved.
-        let rect = self.params.predict_rect_and_init(&self.hook, &self.offset, parent_rect, window);
-        let mut acc_rect = accum_rect.union(&rect);
+        let offset = self.offset.resolve(parent_rect);
+        let rect = self.params.predict_rect_and_init(&self.hook, &offset, parent_rect, window);
+        self.predicted_rect = rect.clone();
+        let mut acc_rect = if self.overlay { accum_rect } else { accum_rect.union(&rect) };
 
         // Recursively get child rects.
         for child in &mut self.children {
@@ -103,6 +210,29 @@ impl LayoutBlock {
         acc_rect
     }
 
+    // Whether any block below this one takes up space.  Notifications where every text block is
+    // empty and there are no images have nothing to show but the background.
+    pub fn has_content(&mut self, window: &NotifyWindow, parent_rect: &Rect) -> bool {
+        let rect = self.params.predict_rect_and_init(&self.hook, &self.offset.resolve(parent_rect), parent_rect, window);
+        self.children.iter_mut()
+            .filter(|child| !child.is_hidden(window))
+            .any(|child| {
+                let offset = child.offset.resolve(&rect);
+                let child_rect = child.params.predict_rect_and_init(&child.hook, &offset, &rect, window);
+                (child_rect.width() > 0.0 && child_rect.height() > 0.0) || child.has_content(window, &rect)
+            })
+    }
+
+    // Find the action of the button block (if any) drawn at `pos`.
+    pub fn find_action_at(&self, pos: &Vec2) -> Option<String> {
+        let action = match &self.params {
+            LayoutElement::ButtonBlock(p) => p.action_at(pos),
+            _ => None,
+        };
+
+        action.or_else(|| self.children.iter().find_map(|child| child.find_action_at(pos)))
+    }
+
     // Call update on each block in tree.
     pub fn update_tree(&mut self, delta_time: Duration, window: &NotifyWindow) -> bool {
         let mut dirty = self.params.update(delta_time, window);
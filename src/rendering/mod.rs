@@ -2,3 +2,4 @@ pub mod window;
 pub mod text;
 pub mod layout;
 pub mod blocks;
+pub mod offscreen;
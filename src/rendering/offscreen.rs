@@ -0,0 +1,147 @@
+// Offscreen rendering, for checking layouts without a running X server.
+// A notification is laid out and drawn to an image surface, then written to a PNG which can be
+// compared against a reference image.
+
+use std::path::Path;
+use std::time::Duration;
+use std::collections::HashMap;
+
+use crate::{
+    config::Config,
+    bus::dbus::Notification,
+    rendering::window::NotifyWindow,
+    rules::{self, RuleOutput},
+};
+
+// How long we run the fake event loop for before drawing.  Time steps are fixed, so animated
+// blocks (like scrolling text) always end up in the same state.
+// Note that `%t` in text blocks uses the real time, so it should be avoided in reference layouts.
+const SIMULATED_TIME: Duration = Duration::from_millis(1000);
+
+// Maximum per-channel difference allowed between two pixels before they're considered different.
+// Antialiasing can differ slightly between cairo/pango versions, so we can't expect an exact match.
+const CHANNEL_TOLERANCE: u8 = 8;
+// Fraction of pixels that are allowed to exceed `CHANNEL_TOLERANCE`.
+const PIXEL_TOLERANCE: f64 = 0.005;
+
+// Render a notification with the given summary and body to `out_path`.
+// If `reference_path` is given, the result is compared against it.
+pub fn render(summary: &str, body: &str, out_path: &Path, reference_path: Option<&Path>) -> Result<(), String> {
+    let cfg = Config::get();
+    let notification = Notification::from_dbus(0, "wired", 0, "", summary, body, vec![], HashMap::new(), -1);
+    let rule_output: RuleOutput = rules::evaluate(&cfg.rules, &notification);
+
+    let mut window = NotifyWindow::new_offscreen(notification, rule_output);
+    window.check_prediction_determinism()?;
+
+    // Drive the window the same way the event loop would.
+    let step = Duration::from_millis(cfg.poll_interval.max(1));
+    let mut elapsed = Duration::default();
+    while elapsed < SIMULATED_TIME {
+        window.update(step);
+        elapsed += step;
+    }
+
+    window.draw();
+    window.write_png(out_path)?;
+
+    match reference_path {
+        Some(reference) => compare(out_path, reference),
+        None => Ok(()),
+    }
+}
+
+// Compare two PNGs, allowing for small differences caused by antialiasing.
+pub fn compare(a: &Path, b: &Path) -> Result<(), String> {
+    let a = image::open(a).map_err(|e| format!("{}: {}", a.display(), e))?.to_rgba();
+    let b = image::open(b).map_err(|e| format!("{}: {}", b.display(), e))?.to_rgba();
+
+    if a.dimensions() != b.dimensions() {
+        return Err(format!("size mismatch: {:?} vs {:?}", a.dimensions(), b.dimensions()));
+    }
+
+    let differing = a.pixels()
+        .zip(b.pixels())
+        .filter(|(pa, pb)| {
+            pa.0.iter()
+                .zip(pb.0.iter())
+                .any(|(ca, cb)| (*ca as i16 - *cb as i16).abs() > CHANNEL_TOLERANCE as i16)
+        })
+        .count();
+
+    let (width, height) = a.dimensions();
+    let total = (width * height) as f64;
+    if differing as f64 > total * PIXEL_TOLERANCE {
+        return Err(format!("{} of {} pixels differ", differing, total));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    // Reference renders live here.  Run the tests with `WIRED_BLESS=1` to write them again after an
+    // intended change to how layouts are drawn; a missing reference is written the same way.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+    fn temp_png(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wired-{}-{}.png", name, std::process::id()))
+    }
+
+    // A grey image, with `changed` pixels in the top row set to `value`.
+    fn write_png(name: &str, width: u32, changed: u32, value: u8) -> PathBuf {
+        let img = ImageBuffer::from_fn(width, 10, |x, y| {
+            if y == 0 && x < changed { Rgba([value, value, value, 255]) } else { Rgba([128, 128, 128, 255]) }
+        });
+        let path = temp_png(name);
+        img.save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn compare_allows_antialiasing_differences() {
+        let a = write_png("aa-a", 100, 0, 0);
+        assert!(compare(&a, &a).is_ok());
+
+        // Every changed pixel is within the channel tolerance.
+        let b = write_png("aa-b", 100, 100, 128 + CHANNEL_TOLERANCE);
+        assert!(compare(&a, &b).is_ok());
+
+        // A few pixels over the channel tolerance, but under the pixel tolerance (0.5% of 1000).
+        let c = write_png("aa-c", 100, 5, 255);
+        assert!(compare(&a, &c).is_ok());
+    }
+
+    #[test]
+    fn compare_catches_real_differences() {
+        let a = write_png("diff-a", 100, 0, 0);
+        let b = write_png("diff-b", 100, 20, 255);
+        assert!(compare(&a, &b).is_err());
+
+        let c = write_png("diff-c", 90, 0, 0);
+        assert!(compare(&a, &c).is_err());
+    }
+
+    #[test]
+    fn default_layout_matches_reference() {
+        Config::init_for_tests();
+        let reference = Path::new(FIXTURES).join("default_layout.png");
+        let out = temp_png("default-layout");
+
+        if std::env::var_os("WIRED_BLESS").is_some() || !reference.exists() {
+            std::fs::create_dir_all(FIXTURES).unwrap();
+            render("Summary", "Body text which is long enough to wrap onto a second line.", &reference, None).unwrap();
+            eprintln!("Wrote reference render to {}.", reference.display());
+            return;
+        }
+
+        render("Summary", "Body text which is long enough to wrap onto a second line.", &out, Some(&reference))
+            .unwrap_or_else(|e| panic!("render doesn't match {}: {}", reference.display(), e));
+    }
+}
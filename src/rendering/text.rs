@@ -5,14 +5,17 @@ use pango::{
     FontDescription,
 };
 
-use serde::Deserialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use serde::{Serialize, Deserialize};
 
 use crate::{
-    maths_utility::{Rect, Vec2},
-    config::{Padding, Color},
+    maths_utility::{self, Rect, Vec2},
+    config::{Config, Padding, Color},
 };
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum EllipsizeMode { NoEllipsize, Start, Middle, End }
 impl Default for EllipsizeMode {
     fn default() -> Self { EllipsizeMode::Middle }
@@ -29,11 +32,32 @@ impl EllipsizeMode {
     }
 }
 
+// How lines of text line up with each other.  In right to left mode, left and right are swapped.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum TextAlignment { Left, Center, Right, Justify }
+impl Default for TextAlignment {
+    fn default() -> Self { TextAlignment::Left }
+}
+
+// Wrap markup so that it's rendered with the given OpenType font features, e.g. "tnum" for
+// tabular figures or "liga=0" to turn off ligatures.
+pub fn with_font_features<'a>(markup: &'a str, features: &[String]) -> Cow<'a, str> {
+    if features.is_empty() {
+        return Cow::Borrowed(markup);
+    }
+
+    Cow::Owned(format!("<span font_features=\"{}\">{}</span>", features.join(", "), markup))
+}
+
 #[derive(Debug)]
 pub struct TextRenderer {
     //config: &'a Config,
     pctx: pango::Context,
     layout: pango::Layout,
+    // Where the cursor was when drawing started, and the extents of each link drawn since, in
+    // device coordinates.  Links are made of one rect per character, so they can span lines.
+    cursor: RefCell<Option<Vec2>>,
+    links: RefCell<Vec<(String, Vec<Rect>)>>,
 }
 
 impl TextRenderer {
@@ -46,7 +70,79 @@ impl TextRenderer {
         Self {
             pctx,
             layout,
+            cursor: RefCell::new(None),
+            links: RefCell::new(vec![]),
+        }
+    }
+
+    // Forget the links from the last draw.  Should be called before drawing a window.
+    pub fn begin_frame(&self, cursor: Option<Vec2>) {
+        *self.cursor.borrow_mut() = cursor;
+        self.links.borrow_mut().clear();
+    }
+
+    // The URL of the link at `pos`, as of the last draw.
+    pub fn link_at(&self, pos: &Vec2) -> Option<String> {
+        self.links.borrow().iter()
+            .find(|(_, rects)| rects.iter().any(|r| {
+                pos.x >= r.left() && pos.x < r.right() && pos.y >= r.top() && pos.y < r.bottom()
+            }))
+            .map(|(url, _)| url.clone())
+    }
+
+    // Record where the links in the current text will be painted, and color them.
+    fn style_links(&self, ctx: &cairo::Context, pos: &Vec2) {
+        let text = match self.layout.get_text() {
+            Some(text) => text.to_string(),
+            None => return,
+        };
+
+        let urls = maths_utility::find_urls(&text);
+        if urls.is_empty() {
+            return;
+        }
+
+        let cfg = Config::get();
+        let attrs = self.layout.get_attributes().unwrap_or_else(pango::AttrList::new);
+        let scale = pango::SCALE as f64;
+        let cursor = self.cursor.borrow();
+        for (start, end) in urls {
+            let rects: Vec<Rect> = text[start..end].char_indices()
+                .map(|(i, _)| {
+                    let r = self.layout.index_to_pos((start + i) as i32);
+                    // Blocks may be transformed (e.g. mirrored), so convert to device coordinates.
+                    let (x0, y0) = ctx.user_to_device(pos.x + r.x as f64 / scale, pos.y + r.y as f64 / scale);
+                    let (x1, y1) = ctx.user_to_device(
+                        pos.x + (r.x + r.width) as f64 / scale,
+                        pos.y + (r.y + r.height) as f64 / scale,
+                    );
+                    Rect::new(x0.min(x1), y0.min(y1), (x1 - x0).abs(), (y1 - y0).abs())
+                })
+                .collect();
+
+            let hovered = cursor.as_ref().map_or(false, |c| {
+                rects.iter().any(|r| c.x >= r.left() && c.x < r.right() && c.y >= r.top() && c.y < r.bottom())
+            });
+            let color = if hovered { cfg.link_hover_color.as_ref().or(cfg.link_color.as_ref()) } else { cfg.link_color.as_ref() };
+
+            let mut styles = vec![];
+            if let Some(c) = color {
+                let channel = |v: f64| (v.max(0.0).min(1.0) * 65535.0) as u16;
+                styles.extend(pango::Attribute::new_foreground(channel(c.r), channel(c.g), channel(c.b)));
+            }
+            if cfg.link_underline {
+                styles.extend(pango::Attribute::new_underline(pango::Underline::Single));
+            }
+            for mut attr in styles {
+                attr.set_start_index(start as u32);
+                attr.set_end_index(end as u32);
+                attrs.insert(attr);
+            }
+
+            self.links.borrow_mut().push((text[start..end].to_owned(), rects));
         }
+
+        self.layout.set_attributes(Some(&attrs));
     }
 
     // Sets the current text of the renderer, applying markup and ellipsizing according to
@@ -61,9 +157,73 @@ impl TextRenderer {
         let height = if max_height < 0 { -1 } else { pango::SCALE * max_height };
 
         self.layout.set_ellipsize(ellipsize.to_pango_mode());
-        self.layout.set_markup(text);
+        // Pango shows nothing at all for invalid markup, so unless we're being strict, show broken
+        // markup (like a stray `<` in a message) as it is instead.
+        if Config::get().strict_markup || pango::parse_markup(text, '\0').is_ok() {
+            self.layout.set_markup(text);
+        } else {
+            self.layout.set_text(text);
+        }
         self.layout.set_height(height);
         self.layout.set_width(width);
+
+        // Reset optional styling, which is set per block after `set_text()`.
+        self.layout.set_wrap(pango::WrapMode::Word);
+
+        // In right to left mode, all text is right aligned.  Pango would otherwise flip the
+        // alignment back for paragraphs that are themselves right to left.
+        let rtl = Config::get().rtl;
+        self.layout.set_auto_dir(!rtl);
+        self.layout.set_alignment(if rtl { pango::Alignment::Right } else { pango::Alignment::Left });
+        self.layout.set_justify(false);
+    }
+
+    // Align the lines of the text.  The size of the text is the same however it's aligned.
+    pub fn set_alignment(&self, alignment: &TextAlignment) {
+        let (start, end) = if Config::get().rtl {
+            (pango::Alignment::Right, pango::Alignment::Left)
+        } else {
+            (pango::Alignment::Left, pango::Alignment::Right)
+        };
+
+        let pango_alignment = match alignment {
+            TextAlignment::Left | TextAlignment::Justify => start,
+            TextAlignment::Center => pango::Alignment::Center,
+            TextAlignment::Right => end,
+        };
+        self.layout.set_alignment(pango_alignment);
+        self.layout.set_justify(match alignment { TextAlignment::Justify => true, _ => false });
+    }
+
+    // Whether the family of a font description is installed, rather than pango quietly falling back
+    // to some other font.
+    pub fn font_available(&self, font: &str) -> bool {
+        let font_dsc = FontDescription::from_string(font);
+        let wanted = match font_dsc.get_family() {
+            Some(family) => family.split(',').next().unwrap_or("").trim().to_lowercase(),
+            None => return true,
+        };
+
+        self.pctx.load_font(&font_dsc)
+            .and_then(|f| f.describe())
+            .and_then(|d| d.get_family())
+            .map_or(false, |family| family.to_lowercase() == wanted)
+    }
+
+    // Allow lines to be broken in the middle of a word if the word doesn't fit on a line by
+    // itself, e.g. for long URLs or hashes.
+    pub fn set_break_anywhere(&self, break_anywhere: bool) {
+        let mode = if break_anywhere { pango::WrapMode::WordChar } else { pango::WrapMode::Word };
+        self.layout.set_wrap(mode);
+    }
+
+    // Limit the text to the first `lines` lines, ellipsizing the last one.
+    pub fn set_max_lines(&self, lines: u32) {
+        // Pango treats a negative height as a number of lines, but only when ellipsizing.
+        if self.layout.get_ellipsize() == pango::EllipsizeMode::None {
+            self.layout.set_ellipsize(pango::EllipsizeMode::End);
+        }
+        self.layout.set_height(-(lines.max(1) as i32));
     }
 
     // Gets a raw, unpadded rect which surrounds the text.
@@ -87,16 +247,65 @@ impl TextRenderer {
 
     // Gets the sized rect, and then applies padding as well.
     pub fn get_sized_padded_rect(&self, padding: &Padding, min_width: i32, min_height: i32) -> Rect {
-        let mut rect = self.get_sized_rect(min_width, min_height);
-
-        rect.set_width(rect.width() as f64 + padding.width());
-        rect.set_height(rect.height() as f64 + padding.height());
+        let mut rect = padding.grow(&self.get_sized_rect(min_width, min_height));
+        rect.set_baseline(padding.top + self.get_baseline());
         rect
     }
 
+    // Distance from the top of the text to the baseline of the first line, in pixels.
+    pub fn get_baseline(&self) -> f64 {
+        self.layout.get_baseline() as f64 / pango::SCALE as f64
+    }
+
+    // Clips the context to the first `fraction` of the words in the current text, which is
+    // positioned as it would be by `paint_padded()`.
+    pub fn clip_revealed_words(&self, ctx: &cairo::Context, pos: &Vec2, padding: &Padding, fraction: f64) {
+        let text = match self.layout.get_text() {
+            Some(text) => text.to_string(),
+            None => return,
+        };
+
+        // Byte index of the end of each word.
+        let mut word_ends = vec![];
+        let mut in_word = false;
+        for (i, c) in text.char_indices() {
+            if c.is_whitespace() {
+                if in_word {
+                    word_ends.push(i);
+                }
+                in_word = false;
+            } else {
+                in_word = true;
+            }
+        }
+        if in_word {
+            word_ends.push(text.len());
+        }
+
+        let revealed_words = (word_ends.len() as f64 * fraction).floor() as usize;
+        let end = if revealed_words == 0 { 0 } else { word_ends[revealed_words - 1] };
+
+        // Add the extents of every revealed character to the clip path.  An empty path clips
+        // everything, which is what we want when no words are revealed yet.
+        let (x, y) = (pos.x + padding.left, pos.y + padding.top);
+        let scale = pango::SCALE as f64;
+        ctx.new_path();
+        for (i, _) in text.char_indices().take_while(|(i, _)| *i < end) {
+            let r = self.layout.index_to_pos(i as i32);
+            ctx.rectangle(
+                x + r.x as f64 / scale,
+                y + r.y as f64 / scale,
+                r.width as f64 / scale,
+                r.height as f64 / scale,
+            );
+        }
+        ctx.clip();
+    }
+
     // Paints current text at the specified position in the specified color.
     pub fn paint(&self, ctx: &cairo::Context, pos: &Vec2, color: &Color) {
         // Move cursor to draw position and draw text.
+        self.style_links(ctx, pos);
         ctx.set_source_rgba(color.r, color.g, color.b, color.a);
         ctx.move_to(pos.x, pos.y);
         pangocairo::functions::show_layout(ctx, &self.layout);
@@ -1,4 +1,5 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::path::Path;
 
 use winit::{
     window::{WindowBuilder, Window},
@@ -8,17 +9,22 @@ use winit::{
 };
 
 use cairo_sys;
-use cairo::{Surface, Context};
+use cairo::{Surface, ImageSurface, Format, Context};
+use chrono::{DateTime, Utc};
 
 use crate::{
-    config::Config,
+    config::{Config, Color, SpawnAnimation},
     management::NotifyWindowManager,
-    rendering::layout::LayoutBlock,
-    maths_utility::{Rect, Vec2},
-    rendering::text::TextRenderer,
-    bus::dbus::Notification,
+    rendering::layout::{LayoutBlock, LayoutElement},
+    maths_utility::{self, Rect, Vec2},
+    rendering::text::{TextRenderer, EllipsizeMode},
+    bus::dbus::{self, Notification, CloseReason, Urgency},
+    x11_utility,
+    rules::RuleOutput,
 };
 
+const COPY_FEEDBACK_FONT: &str = "Sans Bold 12";
+
 // FuseOnly probably won't be used, but it's here for completion's sake.
 bitflags! {
     #[derive(Default)]
@@ -28,6 +34,54 @@ bitflags! {
     }
 }
 
+// State of a window moving into place, see `SpawnAnimation::Spring`.
+#[derive(Debug)]
+struct Spring {
+    position: Vec2,
+    velocity: Vec2,
+    target: Vec2,
+}
+
+impl Spring {
+    // Step the spring forward.  Returns true once it has come to rest at the target.
+    fn step(&mut self, delta_time: Duration) -> bool {
+        let cfg = Config::get();
+
+        // Integrate in small steps, since big ones can make a stiff spring explode.
+        let mut remaining = delta_time.as_secs_f64();
+        while remaining > 0.0 {
+            let dt = remaining.min(0.004);
+            remaining -= dt;
+
+            let ax = cfg.spring_stiffness * (self.target.x - self.position.x) - cfg.spring_damping * self.velocity.x;
+            let ay = cfg.spring_stiffness * (self.target.y - self.position.y) - cfg.spring_damping * self.velocity.y;
+            self.velocity.x += ax * dt;
+            self.velocity.y += ay * dt;
+            self.position.x += self.velocity.x * dt;
+            self.position.y += self.velocity.y * dt;
+        }
+
+        let distance = (self.target.x - self.position.x).hypot(self.target.y - self.position.y);
+        let speed = self.velocity.x.hypot(self.velocity.y);
+        distance < 0.5 && speed < 1.0
+    }
+}
+
+// A run of adjacent notifications from the same app in a stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupInfo {
+    // Whether this is the first (top) notification in the group.
+    pub first: bool,
+    // Number of notifications in the group.
+    pub count: usize,
+}
+
+impl Default for GroupInfo {
+    fn default() -> Self {
+        Self { first: true, count: 1 }
+    }
+}
+
 #[derive(Debug)]
 pub struct NotifyWindow {
     // Context/Surface are placed at the top (in order) so that they are dropped first when a
@@ -37,31 +91,90 @@ pub struct NotifyWindow {
     // Each window has a text renderer to handle all text rendering for that window.
     pub text: TextRenderer,
 
-    pub winit: Window,
+    // Offscreen windows (see `new_offscreen()`) don't have a real window.
+    pub winit: Option<Window>,
     pub notification: Notification,
+    // Merged output of all config rules which matched this notification.
+    pub rule_output: RuleOutput,
 
     // Layout is cloned from config so each notification can have its own mutable copy.
     // This is pretty much just so we can change some params on LayoutBlocks, which is a bit
     // wasteful, but easy.
     pub layout: Option<LayoutBlock>,
 
+    // Set by `close()`.  The window is destroyed once the others have been repositioned.
     pub marked_for_destroy: bool,
+    pub close_reason: CloseReason,
     // Master offset is used to offset all *elements* when drawing.
     // It is useful when the notification expands in either left or top direction.
     pub master_offset: Vec2,
+    // Size of the window's drawing area.  We keep track of this ourselves because winit's
+    // `inner_size()` lags behind `set_size()`, and offscreen windows don't have a winit window.
+    pub size: Vec2,
+    // Index of the monitor the window is on, see `NotifyWindowManager::stack_monitor()`.
+    pub monitor: u32,
+    // The monitor the window is shown on, for sizes given as a percentage of it.
+    pub monitor_rect: Rect,
+    // The focused window when the notification arrived, which it's placed against instead of being
+    // stacked, see `Config::anchor_origin`.
+    pub anchor_rect: Option<Rect>,
+    // Font from the notification's `Config::font_hint`, used instead of the layout's fonts.
+    pub font_override: Option<String>,
     pub fuse: i32,
+    // Total time this window has been alive, independent of the fuse.
+    pub lifetime: Duration,
+    // Set for windows which stay until they're no longer needed, rather than being closed after
+    // `Config::max_lifetime`, like entries in the notification center.
+    pub ignore_max_lifetime: bool,
+    // Where this window sits in its app group, see `Config::stack_by_app`.
+    pub group: GroupInfo,
+    // Notifications shown on this window's card below its own, oldest first, see
+    // `Config::combine_window_ms`.  They're offscreen windows which draw onto this one.
+    pub combined: Vec<NotifyWindow>,
+    // Older notifications hidden behind this one, oldest first, see `Config::group_by`.
+    pub grouped: Vec<(Notification, RuleOutput)>,
+    // Set while the cursor is over the window, to show text in full (see `preview_lines`).
+    pub expanded: bool,
+    // Set the first time the window is hovered.  Blocks can check this to style read and unread
+    // notifications differently, see `LayoutBlock::read_state`.
+    pub read: bool,
+    // Last known position of the cursor within the window, for clicking on buttons.
+    pub cursor_pos: Option<Vec2>,
+    // Minimized windows are just a dot until restored, and don't expire.
+    pub minimized: bool,
+    // Whether the window has been positioned yet, and the spawn animation if it's still moving.
+    placed: bool,
+    spring: Option<Spring>,
+    // Where the window is now and where it's going, which differ while it slides into place after
+    // the stack changes (see `Config::reflow_ms`).  The slide started from `reflow_from`
+    // `reflow_time` ago.
+    current_position: Vec2,
+    target_position: Vec2,
+    reflow_from: Vec2,
+    reflow_time: Duration,
+    // Time left on the highlight pulse, see `Config::group_pulse_color`.
+    pulse: Duration,
+    // Time left showing `Config::copy_feedback_text`.
+    copy_feedback: Duration,
+    // Whether hovering the window stopped its fuse, so it can be restarted when the cursor leaves.
+    hover_paused: bool,
+    // Continuous animations (scrolling text and the group pulse) hold still while this is set, see
+    // `Config::pause_animations_on_battery`.  Set with `set_animations_paused()`.
+    pub animations_paused: bool,
 
-    // `update_enabled` is primarily used for pause functionality right now.
-    //pub update_enabled: bool,
     pub update_mode: UpdateModes,
 }
 
 impl NotifyWindow {
-    pub fn new(el: &EventLoopWindowTarget<()>, notification: Notification, manager: &NotifyWindowManager) -> Self {
+    pub fn new(
+        el: &EventLoopWindowTarget<()>,
+        notification: Notification,
+        rule_output: RuleOutput,
+        manager: &NotifyWindowManager,
+    ) -> Self {
         let cfg = Config::get();
-        // The minimum window width and height is 1.0.  We need this size to generate an initial window.
-        let (width, height)
-            = ((cfg.min_window_width as f64).max(1.0), (cfg.min_window_height as f64).max(1.0));
+        let base_rect = NotifyWindow::base_rect();
+        let (width, height) = (base_rect.width(), base_rect.height());
 
         // @NOTE: this is pretty messed up... It's annoying that winit only exposes a handle to the
         // xlib display through an existing window, which means we have to use a dummy (hidden)
@@ -72,33 +185,55 @@ impl NotifyWindow {
         // we're really using at the moment is the event loop.
         let xlib_display = manager.base_window.xlib_display().expect("Couldn't get xlib_display.");
 
-        let mut visual_info = unsafe {
-            let mut vinfo = std::mem::MaybeUninit::<x11::xlib::XVisualInfo>::uninit();
+        let monitor_id = manager.stack_monitor();
+        let monitor = manager.base_window.available_monitors()
+            .nth(monitor_id as usize)
+            .unwrap_or(manager.base_window.primary_monitor());
+        let (pos, size) = (monitor.position(), monitor.size());
+        let monitor_rect = Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into());
 
-            let status = (x11::xlib::XMatchVisualInfo)(
-                xlib_display as _,
-                x11::xlib::XDefaultScreen(xlib_display as _) as i32,
-                32,
-                x11::xlib::TrueColor,
-                vinfo.as_mut_ptr(),
-            );
+        // Without a compositor, windows with a 32-bit visual are usually drawn as black boxes, so
+        // we only ask for one when transparency is enabled.  Otherwise the screen's default
+        // (opaque) visual is used.
+        let mut visual_info = if cfg.transparency {
+            let visual_info = unsafe {
+                let mut vinfo = std::mem::MaybeUninit::<x11::xlib::XVisualInfo>::uninit();
 
-            if status == 0 {
-                panic!("Couldn't get valid XVisualInfo.");
-            }
+                let status = (x11::xlib::XMatchVisualInfo)(
+                    xlib_display as _,
+                    x11::xlib::XDefaultScreen(xlib_display as _) as i32,
+                    32,
+                    x11::xlib::TrueColor,
+                    vinfo.as_mut_ptr(),
+                );
+
+                if status == 0 {
+                    panic!("Couldn't get valid XVisualInfo.");
+                }
+
+                vinfo.assume_init();
 
-            vinfo.assume_init();
+                vinfo
+            };
 
-            vinfo
+            Some(visual_info)
+        } else {
+            None
         };
 
-        let winit = WindowBuilder::new()
+        let mut builder = WindowBuilder::new()
             .with_inner_size(LogicalSize { width, height })
             .with_x11_window_type(vec![XWindowType::Utility, XWindowType::Notification])
-            .with_title("wired")
-            .with_x11_visual(&mut visual_info)
-            .with_transparent(true)
-            .with_visible(false)    // Window not visible for first draw, because the position will probably be wrong.
+            .with_title(NotifyWindow::title(&notification))
+            .with_visible(false);   // Window not visible for first draw, because the position will probably be wrong.
+
+        if let Some(vinfo) = visual_info.as_mut() {
+            builder = builder
+                .with_x11_visual(vinfo)
+                .with_transparent(true);
+        }
+
+        let winit = builder
             .build(el)
             .expect("Couldn't build winit window.");
 
@@ -107,52 +242,441 @@ impl NotifyWindow {
         //let xlib_display = winit.xlib_display().expect("Couldn't get xlib display.");
         let xlib_window = winit.xlib_window().expect("Couldn't get xlib window.");
 
+        // The window hasn't been mapped yet, so this is our chance to set its initial state.
+        let layer = if rule_output.always_on_bottom.unwrap_or(false) {
+            "_NET_WM_STATE_BELOW"
+        } else {
+            "_NET_WM_STATE_ABOVE"
+        };
+        unsafe {
+            x11_utility::set_wm_state(xlib_display, xlib_window, &[layer]);
+            if !cfg.focusable {
+                x11_utility::set_no_focus(xlib_display, xlib_window);
+            }
+        }
+
         let surface = unsafe {
-            /*
-            let visual = x11::xlib::XDefaultVisual(
-                xlib_display as _,
-                0,
-            );
-            */
+            let visual = match &visual_info {
+                Some(vinfo) => (*vinfo.as_ptr()).visual,
+                None => x11::xlib::XDefaultVisual(
+                    xlib_display as _,
+                    x11::xlib::XDefaultScreen(xlib_display as _),
+                ),
+            };
 
             let sfc_raw = cairo_sys::cairo_xlib_surface_create(
                 xlib_display as _,
                 xlib_window,
-                (*visual_info.as_ptr()).visual,
+                visual,
                 width as _,
                 height as _,
             );
 
             Surface::from_raw_full(sfc_raw)
         };
+        // Creation never returns null; a bad drawable or connection gives a surface in an error
+        // state instead, which `init()` checks for.
+
+        let mut window = NotifyWindow::init(Some(winit), surface, notification, rule_output, monitor_rect, width, height);
+        window.monitor = monitor_id;
+        window.animations_paused = manager.animations_paused;
+        window
+    }
+
+    // Create a window which draws to an image in memory rather than to the screen.  This doesn't
+    // need X11 or an event loop, so it's useful for checking layouts (see `write_png()`).
+    pub fn new_offscreen(notification: Notification, rule_output: RuleOutput) -> Self {
+        // `init()` replaces the surface once it knows how big it should be.
+        let surface = NotifyWindow::create_image_surface(1.0, 1.0);
+        // There's no monitor, so percentage sizes are taken from a common resolution.
+        let monitor_rect = Rect::new(0.0, 0.0, 1920.0, 1080.0);
+        NotifyWindow::init(None, surface, notification, rule_output, monitor_rect, 1.0, 1.0)
+    }
+
+    // Window title, so that window managers and accessibility tools can tell notifications apart.
+    pub fn title(notification: &Notification) -> String {
+        if !Config::get().notification_window_titles {
+            return "wired".to_owned();
+        }
 
+        // Titles are plain text, so drop any markup.
+        let summary = pango::parse_markup(&notification.summary, '\0')
+            .map(|(_, text, _)| text.to_string())
+            .unwrap_or_else(|_| notification.summary.clone());
+
+        format!("{}: {}", notification.app_name, summary)
+    }
+
+    // The rect that notifications grow within.  The minimum window width and height is 1.0.
+    fn base_rect() -> Rect {
+        let cfg = Config::get();
+        Rect::new(0.0, 0.0, (cfg.min_window_width as f64).max(1.0), (cfg.min_window_height as f64).max(1.0))
+    }
+
+    fn create_image_surface(width: f64, height: f64) -> Surface {
+        let sfc = ImageSurface::create(Format::ARgb32, width.max(1.0) as i32, height.max(1.0) as i32)
+            .expect("Couldn't create offscreen image surface.");
+
+        (*sfc).clone()
+    }
+
+    // Shared setup for real and offscreen windows: predict the layout and size the window to fit.
+    fn init(
+        winit: Option<Window>,
+        surface: Surface,
+        mut notification: Notification,
+        rule_output: RuleOutput,
+        monitor_rect: Rect,
+        width: f64,
+        height: f64,
+    ) -> Self {
         let context = cairo::Context::new(&surface);
+        let status = match surface.status() {
+            cairo::Status::Success => context.status(),
+            status => status,
+        };
         let text = TextRenderer::new(&context);
+        notification.timeout = NotifyWindow::capped_timeout(&notification, Config::get().max_timeout);
         let fuse = notification.timeout;
+        // The "+N more" notification has to stay for as long as anything's held back.
+        let ignore_max_lifetime = notification.id == dbus::INTERNAL_ID;
+        let font_override = notification.get_hint_string(&Config::get().font_hint).map(str::to_owned);
+        if let Some(font) = &font_override {
+            if !text.font_available(font) {
+                eprintln!("Warning: font \"{}\" from notification hint isn't installed; a fallback will be used.", font);
+            }
+        }
 
+        let mut update_mode = UpdateModes::all();
+        if notification.never_expires() {
+            update_mode.remove(UpdateModes::FUSE);
+        }
+
+        let layout = Config::get().layout_for(&notification.urgency, rule_output.layout.as_deref()).clone();
         let mut window = Self {
             context,
             surface,
             text,
             winit,
             notification,
-            layout: None,
+            rule_output,
+            layout: Some(layout),
             marked_for_destroy: false,
+            close_reason: CloseReason::Undefined,
             master_offset: Vec2::default(),
+            size: Vec2::new(width, height),
+            monitor: 0,
+            monitor_rect,
+            anchor_rect: None,
+            font_override,
             fuse,
-            update_mode: UpdateModes::all(),
+            lifetime: Duration::default(),
+            ignore_max_lifetime,
+            group: GroupInfo::default(),
+            combined: vec![],
+            grouped: vec![],
+            expanded: false,
+            read: false,
+            cursor_pos: None,
+            minimized: false,
+            placed: false,
+            spring: None,
+            current_position: Vec2::default(),
+            target_position: Vec2::default(),
+            reflow_from: Vec2::default(),
+            reflow_time: Duration::default(),
+            pulse: Duration::default(),
+            copy_feedback: Duration::default(),
+            hover_paused: false,
+            animations_paused: false,
+            update_mode,
         };
 
-        let mut layout = cfg.layout.as_ref().unwrap().clone();
-        let rect = layout.predict_rect_tree_and_init(&window, &window.get_inner_rect(), Rect::empty());
-        let delta = Vec2::new(-rect.x(), -rect.y());
+        // Drawing to a broken surface can crash, so give up on the window instead.  The manager
+        // drops windows that are closed before they're shown.
+        if status != cairo::Status::Success {
+            eprintln!("Couldn't create a cairo surface for notification {}: {:?}", window.notification.id, status);
+            window.close(CloseReason::Undefined);
+            return window;
+        }
 
-        window.layout = Some(layout);
-        window.set_size(rect.width(), rect.height());
-        window.master_offset = delta;
+        window.relayout();
         window
     }
 
+    // The notification's timeout, limited to `max_timeout` (see `Config::max_timeout`).  The "+N
+    // more" notification isn't limited, since it goes away by itself once nothing's held back.
+    fn capped_timeout(notification: &Notification, max_timeout: i32) -> i32 {
+        let uncapped = notification.urgency == Urgency::Critical || notification.id == dbus::INTERNAL_ID;
+        if max_timeout <= 0 || uncapped {
+            return notification.timeout;
+        }
+
+        // 0 means the notification would never expire.
+        match notification.timeout {
+            0 => max_timeout,
+            timeout => timeout.min(max_timeout),
+        }
+    }
+
+    pub fn close(&mut self, reason: CloseReason) {
+        self.marked_for_destroy = true;
+        self.close_reason = reason;
+    }
+
+    // Show a different notification in this window, as if it had just arrived.  The window stays
+    // where it is, but any block state (like scrolling text) starts over.
+    pub fn replace_notification(&mut self, mut notification: Notification, rule_output: RuleOutput) {
+        notification.timeout = NotifyWindow::capped_timeout(&notification, Config::get().max_timeout);
+        self.layout = Some(Config::get().layout_for(&notification.urgency, rule_output.layout.as_deref()).clone());
+        self.fuse = notification.timeout;
+        self.update_mode.set(UpdateModes::FUSE, !notification.never_expires());
+        self.font_override = notification.get_hint_string(&Config::get().font_hint).map(str::to_owned);
+        self.notification = notification;
+        self.rule_output = rule_output;
+
+        if let Some(winit) = &self.winit {
+            winit.set_title(&NotifyWindow::title(&self.notification));
+        }
+        self.relayout();
+    }
+
+    // Show another notification on this window's card, see `Config::combine_window_ms`.  The card
+    // stays up for at least as long as the new notification would have on its own.
+    pub fn combine(&mut self, notification: Notification, rule_output: RuleOutput) {
+        let mut entry = NotifyWindow::new_offscreen(notification, rule_output);
+        entry.surface = self.surface.clone();
+        entry.context = self.context.clone();
+        entry.update_mode.remove(UpdateModes::FUSE);
+        entry.animations_paused = self.animations_paused;
+
+        if entry.notification.never_expires() {
+            self.update_mode.remove(UpdateModes::FUSE);
+        } else {
+            self.fuse = self.fuse.max(entry.notification.timeout);
+        }
+
+        self.combined.push(entry);
+        self.relayout();
+    }
+
+    // Show a newer notification from the same group in this window, keeping the one that was shown
+    // behind it, see `Config::group_by`.
+    pub fn push_grouped(&mut self, notification: Notification, rule_output: RuleOutput) {
+        let previous = (self.notification.clone(), self.rule_output.clone());
+        self.replace_notification(notification, rule_output);
+        self.grouped.push(previous);
+    }
+
+    // Take the notification being shown off the window, showing the next newest in its group, and
+    // return it.  Windows with nothing else grouped in them are left alone.
+    pub fn pop_grouped(&mut self) -> Option<Notification> {
+        let (next, rule_output) = self.grouped.pop()?;
+        let shown = self.notification.clone();
+        self.replace_notification(next, rule_output);
+        Some(shown)
+    }
+
+    // Update a notification grouped behind the one being shown, keeping its place in the group.
+    // Notifications which don't replace anything in the group are ignored.
+    pub fn replace_grouped(&mut self, notification: Notification, rule_output: RuleOutput) {
+        if let Some(entry) = self.grouped.iter_mut().find(|(n, _)| n.id == notification.replaces_id) {
+            *entry = (notification, rule_output);
+        }
+    }
+
+    // When the newest notification on this window's card arrived.
+    pub fn newest_time(&self) -> DateTime<Utc> {
+        self.combined.last().map_or(self.notification.time, |entry| entry.notification.time)
+    }
+
+    // Predict the layout again and resize the window to fit, for when something the layout depends
+    // on has changed.
+    pub fn relayout(&mut self) {
+        // Minimized windows keep their size; they'll be laid out again when restored.
+        if self.minimized {
+            return;
+        }
+
+        let mut layout = self.layout_take();
+        let start = Instant::now();
+        let rect = layout.predict_rect_tree_and_init(&self, &NotifyWindow::base_rect(), Rect::empty());
+        self.log_slow_render("lay out", start);
+        let mut delta = Vec2::new(-rect.x(), -rect.y());
+
+        // Make room to keep to the notification's aspect ratio, if it has one.  The extra space is
+        // split evenly either side, so the content stays centered.
+        let mut size = match &layout.params {
+            LayoutElement::NotificationBlock(p) => p.locked_size(rect.width(), rect.height()),
+            _ => Vec2::new(rect.width(), rect.height()),
+        };
+        delta.x += (size.x - rect.width()) / 2.0;
+        delta.y += (size.y - rect.height()) / 2.0;
+
+        // Combined notifications were laid out when they were added, and go below the rest.
+        for entry in &self.combined {
+            let entry_size = entry.content_size();
+            size.x = size.x.max(entry_size.x);
+            size.y += entry_size.y;
+        }
+
+        // The window has room around the notification for its shadow.
+        let margin = NotifyWindow::shadow_margin();
+        self.layout = Some(layout);
+        self.set_size(size.x + margin * 2.0, size.y + margin * 2.0);
+        self.master_offset = delta;
+
+        match &self.winit {
+            Some(winit) => winit.request_redraw(),
+            // Image surfaces can't be resized, so replace it now that we know how big it should be.
+            None => {
+                self.surface = NotifyWindow::create_image_surface(self.size.x, self.size.y);
+                self.context = cairo::Context::new(&self.surface);
+            },
+        }
+    }
+
+    // In debug mode, report layouts which are slow to render, e.g. because of huge images.
+    fn log_slow_render(&self, what: &str, start: Instant) {
+        let cfg = Config::get();
+        if !cfg.debug {
+            return;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= Duration::from_millis(cfg.debug_render_threshold_ms) {
+            eprintln!(
+                "Notification {} from \"{}\" took {:?} to {}.",
+                self.notification.id, self.notification.app_name, elapsed, what,
+            );
+        }
+    }
+
+    // Layouts are predicted again whenever something changes, so predicting must give the same
+    // result every time.  Blocks which carry state over from an earlier prediction or update break
+    // this, which is easy to miss, so check that a fresh copy of the layout predicts the same as
+    // one which has already been predicted and animated for a while.  This is done by the tests
+    // and `offscreen::render()`, rather than on every relayout.
+    pub fn check_prediction_determinism(&self) -> Result<(), String> {
+        let fresh = || {
+            Config::get().layout_for(&self.notification.urgency, self.rule_output.layout.as_deref()).clone()
+        };
+        let first = fresh().predict_rect_tree_and_init(&self, &NotifyWindow::base_rect(), Rect::empty());
+
+        let mut layout = fresh();
+        layout.predict_rect_tree_and_init(&self, &NotifyWindow::base_rect(), Rect::empty());
+        for _ in 0..50 {
+            layout.update_tree(Duration::from_millis(100), &self);
+        }
+        let second = layout.predict_rect_tree_and_init(&self, &NotifyWindow::base_rect(), Rect::empty());
+
+        if first != second {
+            return Err(format!(
+                "layout prediction for notification from \"{}\" isn't deterministic: {:?} then {:?}",
+                self.notification.app_name, first, second,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Whether the notification has nothing to show apart from the notification block itself.
+    pub fn is_empty(&self) -> bool {
+        let mut layout = self.layout().clone();
+        !layout.has_content(&self, &NotifyWindow::base_rect())
+    }
+
+    // The action of the button under the cursor, if there is one.
+    pub fn action_under_cursor(&self) -> Option<String> {
+        match &self.cursor_pos {
+            Some(pos) if !self.minimized => self.layout().find_action_at(pos),
+            _ => None,
+        }
+    }
+
+    pub fn set_read(&mut self) {
+        if !self.read {
+            self.read = true;
+            // Blocks may be shown or hidden, so the size can change.
+            self.relayout();
+        }
+    }
+
+    // Stop the fuse while the cursor is over the window, see `Config::pause_on_hover`.  Windows
+    // which were already paused are left alone, and stay paused when the cursor leaves.
+    pub fn set_hovered(&mut self, hovered: bool) {
+        if hovered {
+            self.hover_paused = Config::get().pause_on_hover && self.update_mode.contains(UpdateModes::FUSE);
+            if self.hover_paused {
+                self.update_mode.remove(UpdateModes::FUSE);
+            }
+        } else if self.hover_paused {
+            self.hover_paused = false;
+            self.update_mode.insert(UpdateModes::FUSE);
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        // Notifications which never expire can't be unpaused.
+        if self.notification.never_expires() {
+            return;
+        }
+
+        // Hovering has already stopped the fuse, so pausing means keeping it stopped once the
+        // cursor leaves.
+        if self.hover_paused {
+            self.hover_paused = false;
+        } else {
+            self.update_mode.toggle(UpdateModes::FUSE);
+        }
+    }
+
+    pub fn set_expanded(&mut self, expanded: bool) {
+        if self.expanded != expanded {
+            self.expanded = expanded;
+            self.relayout();
+        }
+    }
+
+    pub fn set_minimized(&mut self, minimized: bool) {
+        if self.minimized == minimized {
+            return;
+        }
+
+        self.minimized = minimized;
+        if minimized {
+            let size = Config::get().minimize.size;
+            self.update_mode.remove(UpdateModes::FUSE);
+            self.set_size(size, size);
+            if let Some(winit) = &self.winit {
+                winit.request_redraw();
+            }
+        } else {
+            if !self.notification.never_expires() {
+                self.update_mode.insert(UpdateModes::FUSE);
+            }
+            self.relayout();
+        }
+    }
+
+    pub fn set_group(&mut self, group: GroupInfo) {
+        if self.group != group {
+            self.group = group;
+            self.relayout();
+        }
+    }
+
+    // The winit window.  Only offscreen windows don't have one, and those are never given to the
+    // manager, so anything dealing with real windows can use this.
+    pub fn winit(&self) -> &Window {
+        self.winit.as_ref().expect("Tried to use the winit window of an offscreen window.")
+    }
+
+    // The font text blocks should use, given the one from their config.
+    pub fn font<'a>(&'a self, block_font: &'a str) -> &'a str {
+        self.font_override.as_deref().unwrap_or(block_font)
+    }
+
     pub fn layout(&self) -> &LayoutBlock {
         self.layout.as_ref().unwrap()
     }
@@ -161,34 +685,136 @@ impl NotifyWindow {
         self.layout.take().unwrap()
     }
 
-    pub fn set_position(&self, x: f64, y: f64) {
-        self.winit.set_outer_position(LogicalPosition { x, y });
+    pub fn set_position(&mut self, x: f64, y: f64) {
+        // The first time a window is placed, it might animate into place instead.
+        let first = !self.placed;
+        if first {
+            self.placed = true;
+            if Config::get().spawn_animation == SpawnAnimation::Spring {
+                let offset = &Config::get().spawn_offset;
+                self.spring = Some(Spring {
+                    position: Vec2::new(x + offset.x, y + offset.y),
+                    velocity: Vec2::default(),
+                    target: Vec2::new(x, y),
+                });
+            }
+        }
+
+        // While animating, the spring (or `update()`, when sliding) moves the window, and just
+        // needs to know where to go.
+        let moved = x != self.target_position.x || y != self.target_position.y;
+        let pos = match &mut self.spring {
+            Some(spring) => {
+                spring.target = Vec2::new(x, y);
+                spring.position.clone()
+            },
+            None if Config::get().reflow_ms > 0 && !first => {
+                if moved {
+                    self.reflow_from = self.current_position.clone();
+                    self.reflow_time = Duration::default();
+                }
+                self.current_position.clone()
+            },
+            None => Vec2::new(x, y),
+        };
+        self.target_position = Vec2::new(x, y);
+        self.current_position = pos.clone();
+
+        if let Some(winit) = &self.winit {
+            winit.set_outer_position(LogicalPosition { x: pos.x, y: pos.y });
+        }
     }
 
     pub fn set_visible(&self, visible: bool) {
-        self.winit.set_visible(visible);
+        if let Some(winit) = &self.winit {
+            winit.set_visible(visible);
+        }
     }
 
-    pub fn set_size(&self, width: f64, height: f64) {
-        self.winit.set_inner_size(LogicalSize { width, height });
-        unsafe {
-            cairo_sys::cairo_xlib_surface_set_size(self.surface.to_raw_none(), width as i32, height as i32);
+    // Offscreen windows only record the new size; their surface is recreated by `new_offscreen()`.
+    pub fn set_size(&mut self, width: f64, height: f64) {
+        self.size = Vec2::new(width, height);
+        if let Some(winit) = &self.winit {
+            winit.set_inner_size(LogicalSize { width, height });
+            unsafe {
+                cairo_sys::cairo_xlib_surface_set_size(self.surface.to_raw_none(), width as i32, height as i32);
+            }
         }
     }
 
     // Positioned rect on the desktop.
     pub fn _get_rect(&self) -> Rect {
-        let size = self.winit.inner_size();
-        let pos = self.winit.outer_position().expect("Window no longer exists.");
+        let size = self.winit().inner_size();
+        let pos = self.winit().outer_position().expect("Window no longer exists.");
 
         Rect::new(pos.x.into(), pos.y.into(), size.width.into(), size.height.into())
     }
 
     // Pure rectangle, ignoring the window's position.
     pub fn get_inner_rect(&self) -> Rect {
-        let size = self.winit.inner_size();
+        Rect::new(0.0, 0.0, self.size.x, self.size.y)
+    }
+
+    // Size of the notification itself, without the space around it for its shadow.
+    fn content_size(&self) -> Vec2 {
+        let margin = if self.minimized { 0.0 } else { NotifyWindow::shadow_margin() };
+        Vec2::new(self.size.x - margin * 2.0, self.size.y - margin * 2.0)
+    }
+
+    // Space needed on each side of the notification to fit its shadow.
+    fn shadow_margin() -> f64 {
+        let cfg = Config::get();
+        if cfg.shadow_blur <= 0.0 {
+            return 0.0;
+        }
 
-        Rect::new(0.0, 0.0, size.width.into(), size.height.into())
+        (cfg.shadow_blur + cfg.shadow_offset.x.abs().max(cfg.shadow_offset.y.abs())).ceil()
+    }
+
+    fn draw_shadow(&self) {
+        let cfg = Config::get();
+        let margin = NotifyWindow::shadow_margin();
+        let size = self.content_size();
+        maths_utility::cairo_soft_shadow(
+            &self.context,
+            &Rect::new(margin, margin, size.x, size.y),
+            cfg.corner_radius,
+            &cfg.shadow_offset,
+            cfg.shadow_blur,
+            &cfg.shadow_color,
+        );
+    }
+
+    // Write what has been drawn to a PNG file.  Mostly useful for offscreen windows.
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let (width, height, stride, data) = unsafe {
+            let raw = self.surface.to_raw_none();
+            cairo_sys::cairo_surface_flush(raw);
+
+            let data = cairo_sys::cairo_image_surface_get_data(raw);
+            if data.is_null() {
+                return Err("surface is not an image surface".to_owned());
+            }
+
+            let width = cairo_sys::cairo_image_surface_get_width(raw) as usize;
+            let height = cairo_sys::cairo_image_surface_get_height(raw) as usize;
+            let stride = cairo_sys::cairo_image_surface_get_stride(raw) as usize;
+            (width, height, stride, std::slice::from_raw_parts(data, stride * height))
+        };
+
+        // Cairo stores premultiplied BGRA (on little endian), but PNGs want straight RGBA.
+        let mut pixels: Vec<u8> = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * stride + x * 4;
+                let (b, g, r, a) = (data[i], data[i+1], data[i+2], data[i+3]);
+                let unpremultiply = |c: u8| if a == 0 { 0 } else { (c as u32 * 255 / a as u32).min(255) as u8 };
+                pixels.extend_from_slice(&[unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+            }
+        }
+
+        image::save_buffer(path, &pixels, width as u32, height as u32, image::ColorType::RGBA(8))
+            .map_err(|e| e.to_string())
     }
 
     /*
@@ -203,39 +829,388 @@ impl NotifyWindow {
     */
 
     pub fn draw(&self) {
-        let mut inner_rect = self.get_inner_rect();
+        if self.marked_for_destroy {
+            return;
+        }
+
+        let alpha = self.fade_alpha();
+        if alpha >= 1.0 {
+            self.draw_contents();
+            return;
+        }
+
+        // Draw everything to a group first, so that overlapping blocks fade as one.
+        self.context.push_group();
+        self.draw_contents();
+        self.context.pop_group_to_source();
+
+        self.context.save();
+        self.context.set_operator(cairo::Operator::Clear);
+        self.context.paint();
+        self.context.set_operator(cairo::Operator::Over);
+        self.context.paint_with_alpha(alpha);
+        self.context.restore();
+    }
+
+    // Opacity of the window, from `Config::read_opacity` and fading in after it spawns and out
+    // before its fuse runs out.  This only shows with `Config::transparency` on.
+    fn fade_alpha(&self) -> f64 {
+        // Offscreen renders are a snapshot, which shouldn't come out half transparent.
+        if self.winit.is_none() {
+            return 1.0;
+        }
+
+        let cfg = Config::get();
+        let mut alpha: f64 = if self.read { cfg.read_opacity } else { 1.0 };
+        if cfg.fade_in_ms > 0 {
+            alpha = alpha.min(self.lifetime.as_millis() as f64 / cfg.fade_in_ms as f64);
+        }
+        // Paused windows stay opaque, even if they were paused mid-fade.
+        if cfg.fade_out_ms > 0 && self.update_mode.contains(UpdateModes::FUSE) {
+            alpha = alpha.min(self.fuse.max(0) as f64 / cfg.fade_out_ms as f64);
+        }
+
+        alpha.max(0.0)
+    }
+
+    fn draw_contents(&self) {
+        self.text.begin_frame(self.cursor_pos.clone());
+        if self.minimized {
+            self.draw_minimized();
+            return;
+        }
+
+        let size = self.content_size();
+        let mut inner_rect = Rect::new(0.0, 0.0, size.x, size.y);
         // If the master offset is anything other than `(0.0, 0.0)` it means that one of the
         // blocks is going to expand the big rectangle leftwards and/or upwards, which would
         // cause blocks to be drawn off canvas.
         // To fix this, we offset the initial drawing rect to make sure everything fits in the
         // canvas.
         inner_rect.set_xy(self.master_offset.x, self.master_offset.y);
+
+        // Everything but the shadow is drawn as if the window was only as big as the notification.
+        // Blocks which paint the whole window (e.g. the notification block) are clipped so they
+        // don't paint over the shadow.
+        let margin = NotifyWindow::shadow_margin();
+        if margin > 0.0 {
+            self.context.save();
+            self.context.set_operator(cairo::Operator::Clear);
+            self.context.paint();
+            self.context.restore();
+            self.draw_shadow();
+
+            self.context.save();
+            self.context.translate(margin, margin);
+            self.context.rectangle(0.0, 0.0, size.x, size.y);
+            self.context.clip();
+        }
+
+        // Round the corners by clipping everything to a rounded rect the size of the notification,
+        // which is what the notification block's background fills regardless of `master_offset`.
+        // The corners are cleared first (if the shadow hasn't already), because they won't be drawn
+        // over.
+        let corner_radius = Config::get().corner_radius;
+        if corner_radius > 0.0 {
+            if margin <= 0.0 {
+                self.context.save();
+                self.context.set_operator(cairo::Operator::Clear);
+                self.context.paint();
+                self.context.restore();
+            }
+
+            self.context.save();
+            maths_utility::cairo_rounded_rectangle(&self.context, 0.0, 0.0, size.x, size.y, corner_radius);
+            self.context.clip();
+        }
+
+        let start = Instant::now();
         self.layout().draw_tree(self, &inner_rect, Rect::empty());
+        self.draw_combined(&size);
+        self.log_slow_render("draw", start);
+
+        self.draw_pulse();
+        self.draw_copy_feedback();
+
+        if corner_radius > 0.0 {
+            self.context.restore();
+        }
+        if margin > 0.0 {
+            self.context.restore();
+        }
+    }
+
+    // Draw the notifications combined into this card one after another below its own content.
+    // Only the blocks below each one's notification block are drawn, which is anchored to a point
+    // (see `NotificationBlockParameters`), so they're given that point as their parent.
+    fn draw_combined(&self, size: &Vec2) {
+        let mut y = size.y - self.combined.iter().map(|entry| entry.content_size().y).sum::<f64>();
+        for entry in &self.combined {
+            entry.text.begin_frame(None);
+            let parent_rect = Rect::new(entry.master_offset.x, y + entry.master_offset.y, 0.0, 0.0);
+            for child in &entry.layout().children {
+                child.draw_tree(entry, &parent_rect, Rect::empty());
+            }
+
+            y += entry.content_size().y;
+        }
+    }
+
+    pub fn show_copy_feedback(&mut self) {
+        let cfg = Config::get();
+        if !cfg.copy_feedback_text.is_empty() {
+            self.copy_feedback = Duration::from_millis(cfg.copy_feedback_ms);
+            if let Some(winit) = &self.winit {
+                winit.request_redraw();
+            }
+        }
+    }
+
+    // Dim the notification and show the copy confirmation in the middle of it.
+    fn draw_copy_feedback(&self) {
+        if self.copy_feedback == Duration::default() {
+            return;
+        }
+
+        self.context.save();
+        self.context.set_operator(cairo::Operator::Atop);
+        self.context.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+        self.context.paint();
+
+        self.context.set_operator(cairo::Operator::Over);
+        self.text.set_text(&Config::get().copy_feedback_text, COPY_FEEDBACK_FONT, -1, -1, &EllipsizeMode::NoEllipsize);
+        let text_rect = self.text.get_sized_rect(0, 0);
+        let size = self.content_size();
+        let pos = Vec2::new((size.x - text_rect.width()) / 2.0, (size.y - text_rect.height()) / 2.0);
+        self.text.paint(&self.context, &pos, &Color::from_rgba(1.0, 1.0, 1.0, 1.0));
+        self.context.restore();
+    }
+
+    pub fn set_animations_paused(&mut self, paused: bool) {
+        self.animations_paused = paused;
+        for entry in &mut self.combined {
+            entry.animations_paused = paused;
+        }
+        if paused {
+            self.pulse = Duration::default();
+        }
+        if let Some(winit) = &self.winit {
+            winit.request_redraw();
+        }
+    }
+
+    pub fn start_pulse(&mut self) {
+        if Config::get().group_pulse_color.is_some() && !self.animations_paused {
+            self.pulse = Duration::from_millis(Config::get().group_pulse_time_ms);
+        }
+    }
+
+    // Highlight over the whole notification, fading out as the pulse runs out.
+    fn draw_pulse(&self) {
+        let cfg = Config::get();
+        let c = match &cfg.group_pulse_color {
+            Some(c) if self.pulse > Duration::default() => c,
+            _ => return,
+        };
+
+        let t = self.pulse.as_secs_f64() / Duration::from_millis(cfg.group_pulse_time_ms.max(1)).as_secs_f64();
+        self.context.save();
+        // `Atop` only draws where the notification has already drawn, so rounded corners stay clear.
+        self.context.set_operator(cairo::Operator::Atop);
+        self.context.set_source_rgba(c.r, c.g, c.b, c.a * t.min(1.0));
+        self.context.paint();
+        self.context.restore();
+    }
+
+    fn draw_minimized(&self) {
+        let cfg = Config::get();
+        let c = &cfg.minimize.color;
+        let radius = self.size.x.min(self.size.y) / 2.0;
+
+        self.context.set_operator(cairo::Operator::Clear);
+        self.context.paint();
+        self.context.set_operator(cairo::Operator::Over);
+        self.context.set_source_rgba(c.r, c.g, c.b, c.a);
+        self.context.arc(self.size.x / 2.0, self.size.y / 2.0, radius, 0.0, 2.0 * std::f64::consts::PI);
+        self.context.fill();
     }
 
     pub fn update(&mut self, delta_time: Duration) -> bool {
         let mut dirty = false;
 
+        // Enforce max lifetime regardless of update mode, so paused or never-expiring notifications
+        // can't get stuck on screen forever.
+        self.lifetime += delta_time;
+        let max_lifetime = Config::get().max_lifetime;
+        if max_lifetime > 0 && !self.ignore_max_lifetime && self.lifetime.as_millis() as u64 >= max_lifetime {
+            println!(
+                "Notification from \"{}\" exceeded max_lifetime ({}ms) and was closed.",
+                self.notification.app_name, max_lifetime,
+            );
+            self.close(CloseReason::Expired);
+            return true
+        }
+
         if self.update_mode.contains(UpdateModes::FUSE) {
             self.fuse -= delta_time.as_millis() as i32;
             if self.fuse <= 0 {
+                let invoke_default = self.rule_output.invoke_default_on_expiry
+                    .unwrap_or(Config::get().invoke_default_on_expiry);
+                if invoke_default && self.notification.get_default_action().is_some() {
+                    dbus::signal_action_invoked(self.notification.id, "default");
+                }
+
                 // Window will be destroyed after others have been repositioned to replace it.
                 // We can return early because drawing will be discarded anyway.
-                self.marked_for_destroy = true;
+                self.close(CloseReason::Expired);
                 return true
             }
         }
 
-        if self.update_mode.contains(UpdateModes::DRAW) {
+        // Moving the window doesn't need a redraw.
+        if let Some(spring) = &mut self.spring {
+            let settled = spring.step(delta_time);
+            let pos = if settled { spring.target.clone() } else { spring.position.clone() };
+            if settled {
+                self.spring = None;
+            }
+
+            self.current_position = pos.clone();
+            if let Some(winit) = &self.winit {
+                winit.set_outer_position(LogicalPosition { x: pos.x, y: pos.y });
+            }
+        } else if self.current_position.x != self.target_position.x || self.current_position.y != self.target_position.y {
+            // Slide towards the new position, slowing down on the way in.
+            self.reflow_time += delta_time;
+            let reflow_ms = Config::get().reflow_ms;
+            let t = if reflow_ms == 0 {
+                1.0
+            } else {
+                (self.reflow_time.as_millis() as f64 / reflow_ms as f64).min(1.0)
+            };
+            let t = 1.0 - (1.0 - t).powi(3);
+
+            let (from, to) = (&self.reflow_from, &self.target_position);
+            self.current_position = Vec2::new(maths_utility::lerp(from.x, to.x, t), maths_utility::lerp(from.y, to.y, t));
+            if t >= 1.0 {
+                self.current_position = to.clone();
+            }
+
+            if let Some(winit) = &self.winit {
+                let pos = &self.current_position;
+                winit.set_outer_position(LogicalPosition { x: pos.x, y: pos.y });
+            }
+        }
+
+        // Redraw while fading.  The fuse runs out as the fade out finishes, so the window is only
+        // destroyed once it's fully transparent.
+        let cfg = Config::get();
+        let fading_in = cfg.fade_in_ms > 0 && (self.lifetime - delta_time).as_millis() < cfg.fade_in_ms as u128;
+        let fading_out = cfg.fade_out_ms > 0 &&
+            self.update_mode.contains(UpdateModes::FUSE) &&
+            self.fuse <= cfg.fade_out_ms as i32;
+        if fading_in || fading_out {
+            if let Some(winit) = &self.winit {
+                winit.request_redraw();
+            }
+        }
+
+        // Keep redrawing while the pulse fades, including once more after it's finished.
+        if self.pulse > Duration::default() {
+            self.pulse = self.pulse.checked_sub(delta_time).unwrap_or_default();
+            dirty = true;
+        }
+
+        // Redraw once the copy confirmation has gone.  This doesn't change the size of the window,
+        // so it isn't reported as dirty.
+        if self.copy_feedback > Duration::default() {
+            self.copy_feedback = self.copy_feedback.checked_sub(delta_time).unwrap_or_default();
+            if self.copy_feedback == Duration::default() {
+                if let Some(winit) = &self.winit {
+                    winit.request_redraw();
+                }
+            }
+        }
+
+        // The layout isn't shown while minimized, so there's nothing to animate.
+        if self.update_mode.contains(UpdateModes::DRAW) && !self.minimized {
             let mut layout = self.layout_take();
             dirty |= layout.update_tree(delta_time, &self);
             self.layout = Some(layout);
+
+            for entry in &mut self.combined {
+                dirty |= entry.update(delta_time);
+            }
         }
 
         if dirty {
-            self.winit.request_redraw();
+            if let Some(winit) = &self.winit {
+                winit.request_redraw();
+            }
         }
 
         dirty
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn notification(id: u32, timeout: i32) -> Notification {
+        Config::init_for_tests();
+        Notification::from_dbus(id, "app", 0, "", "Summary", "Body", vec![], HashMap::new(), timeout)
+    }
+
+    fn from_chat(id: u32, replaces_id: u32, summary: &str) -> Notification {
+        Config::init_for_tests();
+        Notification::from_dbus(id, "chat", replaces_id, "", summary, "Body", vec![], HashMap::new(), -1)
+    }
+
+    #[test]
+    fn replacing_grouped_notification_keeps_its_place() {
+        let mut window = NotifyWindow::new_offscreen(from_chat(1, 0, "One"), RuleOutput::default());
+        window.push_grouped(from_chat(2, 0, "Two"), RuleOutput::default());
+        window.push_grouped(from_chat(3, 0, "Three"), RuleOutput::default());
+
+        window.replace_grouped(from_chat(1, 1, "One again"), RuleOutput::default());
+        assert_eq!(window.notification.summary, "Three");
+        assert_eq!(window.grouped.len(), 2);
+
+        // Ungrouping shows the replacement, not the original.
+        assert_eq!(window.pop_grouped().map(|n| n.id), Some(3));
+        assert_eq!(window.pop_grouped().map(|n| n.id), Some(2));
+        assert_eq!(window.notification.summary, "One again");
+        assert!(window.pop_grouped().is_none());
+    }
+
+    #[test]
+    fn prediction_is_deterministic() {
+        Config::init_for_tests();
+        // The default layout's body scrolls when it's too long.
+        let body = "A body which is far too long to fit in the notification, so it has to scroll";
+        let notification = Notification::from_dbus(1, "app", 0, "", "Summary", body, vec![], HashMap::new(), -1);
+        let window = NotifyWindow::new_offscreen(notification, RuleOutput::default());
+        assert_eq!(window.check_prediction_determinism(), Ok(()));
+    }
+
+    #[test]
+    fn max_timeout_caps_timeouts() {
+        assert_eq!(NotifyWindow::capped_timeout(&notification(1, 0), 60_000), 60_000);
+        assert_eq!(NotifyWindow::capped_timeout(&notification(1, 90_000), 60_000), 60_000);
+        assert_eq!(NotifyWindow::capped_timeout(&notification(1, 5_000), 60_000), 5_000);
+        assert_eq!(NotifyWindow::capped_timeout(&notification(1, 0), 0), 0);
+    }
+
+    #[test]
+    fn overflow_indicator_is_never_capped() {
+        let indicator = notification(dbus::INTERNAL_ID, 0);
+        assert_eq!(NotifyWindow::capped_timeout(&indicator, 60_000), 0);
+
+        let window = NotifyWindow::new_offscreen(indicator, RuleOutput::default());
+        assert!(window.ignore_max_lifetime);
+        assert!(!window.update_mode.contains(UpdateModes::FUSE));
+    }
+}
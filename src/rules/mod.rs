@@ -0,0 +1,450 @@
+// Rules let users change how notifications are handled based on their contents.
+//
+// Every rule whose criteria match a notification contributes its output.  When more than one
+// matching rule sets the same output field, the rule with the highest `priority` wins; between
+// rules with equal priority, the one listed first in the config wins.  Fields are merged one by one,
+// so e.g. one rule can set the timeout while another sets the colors.
+//
+// Example:
+// rules: [
+//     (priority: 1, criteria: (urgency: Critical), output: (border_color: Color(hex: "#fb4934"))),
+//     (priority: 0, criteria: (app_name: "Spotify"), output: (timeout: 3000)),
+//     (priority: 0, criteria: (fields: ["hint:value", "category~volume"]), output: (timeout: 1000)),
+//     (priority: 0, criteria: (app_name: "Spotify"), output: (layout: "music")),
+// ],
+
+use std::fmt;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::bus::dbus::{Notification, Urgency};
+use crate::config::{Color, AnchorOrigin};
+use crate::maths_utility::Vec2;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rule {
+    #[serde(default)]
+    pub priority: i32,
+    pub criteria: RuleCriteria,
+    pub output: RuleOutput,
+}
+
+// All criteria which are present must match for the rule to apply.  A rule with no criteria matches
+// every notification.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RuleCriteria {
+    // Must be equal to the notification's app name.
+    pub app_name: Option<String>,
+    // Must be contained in the notification's summary/body.
+    pub summary: Option<String>,
+    pub body: Option<String>,
+    pub urgency: Option<Urgency>,
+    // Conditions on any other field, see `FieldMatcher`.
+    #[serde(default)]
+    pub fields: Vec<FieldMatcher>,
+}
+
+// A field of a notification which rules can match on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    AppName,
+    Summary,
+    Body,
+    Category,
+    DesktopEntry,
+    // Any hint, by name.
+    Hint(String),
+    // An action, by key.  Its value is the action's label.
+    Action(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchOp {
+    Present,
+    Equals,
+    NotEquals,
+    Contains,
+}
+
+// A condition on a single field, written as a string in the config:
+//   "field"          the field is present
+//   "field=text"     the field is exactly `text`
+//   "field!=text"    the field isn't exactly `text`, or isn't present
+//   "field~text"     the field contains `text`
+//
+// Available fields are `app_name`, `summary`, `body`, `category`, `desktop-entry`, `hint:<name>`
+// and `action:<key>`.  E.g. "hint:value" matches anything showing progress, and "action:default"
+// matches notifications which can be clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMatcher {
+    pub field: Field,
+    pub op: MatchOp,
+    pub text: String,
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Self, String> {
+        let field = match name {
+            "app_name" => Field::AppName,
+            "summary" => Field::Summary,
+            "body" => Field::Body,
+            "category" => Field::Category,
+            "desktop-entry" => Field::DesktopEntry,
+            _ if name.starts_with("hint:") => Field::Hint(name["hint:".len()..].to_owned()),
+            _ if name.starts_with("action:") => Field::Action(name["action:".len()..].to_owned()),
+            _ => return Err(format!("Unknown notification field \"{}\".", name)),
+        };
+
+        Ok(field)
+    }
+
+    // The field's value, or `None` if the notification doesn't have it.
+    fn value(&self, notification: &Notification) -> Option<String> {
+        match self {
+            Field::AppName => Some(notification.app_name.clone()),
+            Field::Summary => Some(notification.summary.clone()),
+            Field::Body => Some(notification.body.clone()),
+            Field::Category => notification.get_hint_text("category"),
+            Field::DesktopEntry => notification.get_hint_text("desktop-entry"),
+            Field::Hint(name) => notification.get_hint_text(name),
+            Field::Action(key) => notification.actions.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, label)| label.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::AppName => write!(f, "app_name"),
+            Field::Summary => write!(f, "summary"),
+            Field::Body => write!(f, "body"),
+            Field::Category => write!(f, "category"),
+            Field::DesktopEntry => write!(f, "desktop-entry"),
+            Field::Hint(name) => write!(f, "hint:{}", name),
+            Field::Action(key) => write!(f, "action:{}", key),
+        }
+    }
+}
+
+impl FieldMatcher {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let (name, op, text) = match expr.find(|c| c == '=' || c == '~') {
+            Some(i) if expr[i..].starts_with('~') => (&expr[..i], MatchOp::Contains, &expr[i + 1..]),
+            Some(i) if expr[..i].ends_with('!') => (&expr[..i - 1], MatchOp::NotEquals, &expr[i + 1..]),
+            Some(i) => (&expr[..i], MatchOp::Equals, &expr[i + 1..]),
+            None => (expr, MatchOp::Present, ""),
+        };
+
+        Ok(FieldMatcher {
+            field: Field::parse(name.trim())?,
+            op,
+            text: text.to_owned(),
+        })
+    }
+
+    pub fn matches(&self, notification: &Notification) -> bool {
+        let value = self.field.value(notification);
+        match self.op {
+            MatchOp::Present => value.is_some(),
+            MatchOp::Equals => value.map_or(false, |v| v == self.text),
+            MatchOp::NotEquals => value.map_or(true, |v| v != self.text),
+            MatchOp::Contains => value.map_or(false, |v| v.contains(self.text.as_str())),
+        }
+    }
+}
+
+impl fmt::Display for FieldMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.op {
+            MatchOp::Present => write!(f, "{}", self.field),
+            MatchOp::Equals => write!(f, "{}={}", self.field, self.text),
+            MatchOp::NotEquals => write!(f, "{}!={}", self.field, self.text),
+            MatchOp::Contains => write!(f, "{}~{}", self.field, self.text),
+        }
+    }
+}
+
+impl Serialize for FieldMatcher {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldMatcher {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let expr = String::deserialize(deserializer)?;
+        FieldMatcher::parse(&expr).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RuleOutput {
+    pub timeout: Option<i32>,
+    pub background_color: Option<Color>,
+    pub border_color: Option<Color>,
+    // Keep the notification below other windows instead of above them, e.g. for ambient info.
+    pub always_on_bottom: Option<bool>,
+    // When a notification arrives with the same app name and summary as one that's already shown,
+    // show it in place of the old one rather than alongside it.  For apps which send a new
+    // notification every time something changes, instead of using `replaces_id`.
+    pub replace_duplicates: Option<bool>,
+    // Show the notification at this position on the screen (like the `x` and `y` hints), instead of
+    // in the stack.
+    pub pin_position: Option<Vec2>,
+    // What happens when another notification is pinned to the same position.
+    pub pin_stacking: Option<PinStacking>,
+    // See `Config::invoke_default_on_expiry`.
+    pub invoke_default_on_expiry: Option<bool>,
+    // See `Config::anchor_origin`.
+    pub anchor_origin: Option<AnchorOrigin>,
+    // Draw the notification with this layout from `Config::layouts`.
+    pub layout: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum PinStacking {
+    // Offset each new notification by `Config::position_hint_cascade_offset`.
+    Cascade,
+    // Close the notification that was there before.
+    Replace,
+}
+
+impl Default for PinStacking {
+    fn default() -> Self { PinStacking::Cascade }
+}
+
+impl RuleCriteria {
+    pub fn matches(&self, notification: &Notification) -> bool {
+        if let Some(app_name) = &self.app_name {
+            if *app_name != notification.app_name {
+                return false;
+            }
+        }
+
+        if let Some(summary) = &self.summary {
+            if !notification.summary.contains(summary.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(body) = &self.body {
+            if !notification.body.contains(body.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(urgency) = &self.urgency {
+            if *urgency != notification.urgency {
+                return false;
+            }
+        }
+
+        self.fields.iter().all(|f| f.matches(notification))
+    }
+}
+
+impl RuleOutput {
+    // Fill in any fields which haven't been set yet from `other`.
+    fn merge_from(&mut self, other: &RuleOutput) {
+        if self.timeout.is_none() {
+            self.timeout = other.timeout;
+        }
+        if self.background_color.is_none() {
+            self.background_color = other.background_color.clone();
+        }
+        if self.border_color.is_none() {
+            self.border_color = other.border_color.clone();
+        }
+        if self.always_on_bottom.is_none() {
+            self.always_on_bottom = other.always_on_bottom;
+        }
+        if self.replace_duplicates.is_none() {
+            self.replace_duplicates = other.replace_duplicates;
+        }
+        if self.pin_position.is_none() {
+            self.pin_position = other.pin_position.clone();
+        }
+        if self.pin_stacking.is_none() {
+            self.pin_stacking = other.pin_stacking.clone();
+        }
+        if self.invoke_default_on_expiry.is_none() {
+            self.invoke_default_on_expiry = other.invoke_default_on_expiry;
+        }
+        if self.anchor_origin.is_none() {
+            self.anchor_origin = other.anchor_origin.clone();
+        }
+        if self.layout.is_none() {
+            self.layout = other.layout.clone();
+        }
+    }
+}
+
+// Evaluate all rules against a notification, merging the outputs of every matching rule.
+pub fn evaluate(rules: &[Rule], notification: &Notification) -> RuleOutput {
+    let mut matched: Vec<&Rule> = rules.iter()
+        .filter(|r| r.criteria.matches(notification))
+        .collect();
+
+    // Highest priority first.  The sort is stable, so equal priorities keep config order.
+    matched.sort_by(|a, b| b.priority.cmp(&a.priority));
+
+    let mut output = RuleOutput::default();
+    for rule in matched {
+        output.merge_from(&rule.output);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::bus::dbus::Value;
+    use crate::config::Config;
+
+    fn notification(app_name: &str) -> Notification {
+        Config::init_for_tests();
+        Notification::from_dbus(1, app_name, 0, "", "Summary", "Body", vec![], HashMap::new(), -1)
+    }
+
+    fn with_hints(hints: Vec<(&str, Value)>, actions: Vec<&str>) -> Notification {
+        Config::init_for_tests();
+        let hints = hints.into_iter().map(|(k, v)| (k.to_owned(), v)).collect();
+        Notification::from_dbus(1, "app", 0, "", "Summary", "Body", actions, hints, -1)
+    }
+
+    fn matches(expr: &str, notification: &Notification) -> bool {
+        FieldMatcher::parse(expr).unwrap().matches(notification)
+    }
+
+    fn rule(priority: i32, app_name: Option<&str>, output: RuleOutput) -> Rule {
+        let criteria = RuleCriteria { app_name: app_name.map(str::to_owned), ..Default::default() };
+        Rule { priority, criteria, output }
+    }
+
+    fn timeout(timeout: i32) -> RuleOutput {
+        RuleOutput { timeout: Some(timeout), ..Default::default() }
+    }
+
+    #[test]
+    fn higher_priority_wins() {
+        let rules = vec![
+            rule(0, None, timeout(1000)),
+            rule(5, Some("app"), timeout(3000)),
+            rule(2, None, timeout(2000)),
+        ];
+
+        assert_eq!(evaluate(&rules, &notification("app")).timeout, Some(3000));
+        assert_eq!(evaluate(&rules, &notification("other")).timeout, Some(2000));
+    }
+
+    #[test]
+    fn equal_priority_keeps_config_order() {
+        let rules = vec![
+            rule(1, None, timeout(1000)),
+            rule(1, None, timeout(2000)),
+            rule(0, None, timeout(3000)),
+        ];
+        assert_eq!(evaluate(&rules, &notification("app")).timeout, Some(1000));
+
+        let rules = vec![
+            rule(1, None, timeout(2000)),
+            rule(1, None, timeout(1000)),
+        ];
+        assert_eq!(evaluate(&rules, &notification("app")).timeout, Some(2000));
+    }
+
+    #[test]
+    fn fields_merge_from_different_rules() {
+        let layout = RuleOutput { layout: Some("big".to_owned()), timeout: Some(500), ..Default::default() };
+        let rules = vec![
+            rule(0, Some("app"), layout),
+            rule(1, None, timeout(1000)),
+            rule(2, None, RuleOutput { replace_duplicates: Some(true), ..Default::default() }),
+        ];
+
+        let output = evaluate(&rules, &notification("app"));
+        assert_eq!(output.timeout, Some(1000));
+        assert_eq!(output.layout.as_deref(), Some("big"));
+        assert_eq!(output.replace_duplicates, Some(true));
+    }
+
+    #[test]
+    fn no_matching_rules() {
+        let rules = vec![rule(0, Some("app"), timeout(1000))];
+        let output = evaluate(&rules, &notification("other"));
+        assert!(output.timeout.is_none());
+        assert!(output.layout.is_none());
+    }
+
+    #[test]
+    fn parse_matchers() {
+        let parse = |expr| FieldMatcher::parse(expr).unwrap();
+        assert_eq!(parse("category"), FieldMatcher { field: Field::Category, op: MatchOp::Present, text: "".to_owned() });
+        assert_eq!(parse("app_name=Spotify"), FieldMatcher { field: Field::AppName, op: MatchOp::Equals, text: "Spotify".to_owned() });
+        assert_eq!(parse("hint:x-tag!=volume"), FieldMatcher {
+            field: Field::Hint("x-tag".to_owned()), op: MatchOp::NotEquals, text: "volume".to_owned(),
+        });
+        assert_eq!(parse("action:default~Open"), FieldMatcher {
+            field: Field::Action("default".to_owned()), op: MatchOp::Contains, text: "Open".to_owned(),
+        });
+        // Only the first operator counts, so the text can contain `=` or `~`.
+        assert_eq!(parse("body=a=b~c").text, "a=b~c");
+        assert_eq!(parse("summary~").text, "");
+
+        assert!(FieldMatcher::parse("nonsense=1").is_err());
+        assert!(FieldMatcher::parse("").is_err());
+    }
+
+    #[test]
+    fn matchers_round_trip() {
+        for expr in &["desktop-entry", "hint:value=50", "summary!=Hi", "action:reply~Re"] {
+            assert_eq!(FieldMatcher::parse(expr).unwrap().to_string(), *expr);
+        }
+    }
+
+    #[test]
+    fn match_fields() {
+        let n = with_hints(
+            vec![("category", Value::String("device.added".to_owned())), ("value", Value::I32(50))],
+            vec!["default", "Open", "reply", "Reply"],
+        );
+
+        assert!(matches("category", &n));
+        assert!(matches("category=device.added", &n));
+        assert!(!matches("category=device", &n));
+        assert!(matches("category~device", &n));
+        assert!(matches("category!=device", &n));
+        assert!(!matches("category!=device.added", &n));
+
+        assert!(matches("hint:value=50", &n));
+        assert!(matches("app_name=app", &n));
+        assert!(matches("body~od", &n));
+        assert!(matches("action:default", &n));
+        assert!(matches("action:reply=Reply", &n));
+        assert!(!matches("action:dismiss", &n));
+    }
+
+    #[test]
+    fn match_missing_hint() {
+        let n = with_hints(vec![], vec![]);
+
+        assert!(!matches("hint:value", &n));
+        assert!(!matches("hint:value=50", &n));
+        assert!(!matches("hint:value~5", &n));
+        // A missing field isn't equal to anything.
+        assert!(matches("hint:value!=50", &n));
+        assert!(!matches("desktop-entry", &n));
+        assert!(!matches("action:default", &n));
+    }
+}
@@ -0,0 +1,228 @@
+#![allow(dead_code)]
+
+// Small helpers for the raw xlib calls that winit doesn't expose.
+// All of these take the display/window handles that winit gives us via `WindowExtUnix`.
+
+use std::ffi::CString;
+use std::os::raw::{c_long, c_ulong, c_uchar, c_void};
+use std::ptr;
+
+use x11::xlib;
+
+pub unsafe fn intern_atom(display: *mut c_void, name: &str) -> xlib::Atom {
+    let name = CString::new(name).expect("Atom name contained a null byte.");
+    xlib::XInternAtom(display as _, name.as_ptr(), xlib::False)
+}
+
+// Tell the window manager that this window doesn't want keyboard input, so that mapping it won't
+// steal focus from whatever the user is doing.
+// Must be called before the window is mapped.
+pub unsafe fn set_no_focus(display: *mut c_void, window: xlib::Window) {
+    let hints = xlib::XAllocWMHints();
+    if !hints.is_null() {
+        (*hints).flags = xlib::InputHint;
+        (*hints).input = xlib::False;
+        xlib::XSetWMHints(display as _, window, hints);
+        xlib::XFree(hints as _);
+    }
+
+    // A user time of 0 means "don't focus this window when it's mapped" (see `_NET_WM_USER_TIME`
+    // in the EWMH spec).
+    let user_time: c_long = 0;
+    xlib::XChangeProperty(
+        display as _,
+        window,
+        intern_atom(display, "_NET_WM_USER_TIME"),
+        xlib::XA_CARDINAL,
+        32,
+        xlib::PropModeReplace,
+        &user_time as *const c_long as *const u8,
+        1,
+    );
+}
+
+// Set the initial `_NET_WM_STATE` of a window, e.g. `["_NET_WM_STATE_ABOVE"]`.
+// Must be called before the window is mapped; after that, the window manager owns the property.
+pub unsafe fn set_wm_state(display: *mut c_void, window: xlib::Window, states: &[&str]) {
+    let atoms: Vec<xlib::Atom> = states.iter().map(|s| intern_atom(display, s)).collect();
+    xlib::XChangeProperty(
+        display as _,
+        window,
+        intern_atom(display, "_NET_WM_STATE"),
+        xlib::XA_ATOM,
+        32,
+        xlib::PropModeReplace,
+        atoms.as_ptr() as *const u8,
+        atoms.len() as i32,
+    );
+}
+
+// Where the mouse pointer is, relative to the top left of the screen.
+pub unsafe fn pointer_position(display: *mut c_void) -> Option<(i32, i32)> {
+    let root = xlib::XDefaultRootWindow(display as _);
+    let (mut root_ret, mut child_ret): (xlib::Window, xlib::Window) = (0, 0);
+    let (mut root_x, mut root_y, mut win_x, mut win_y) = (0, 0, 0, 0);
+    let mut mask = 0;
+    let on_screen = xlib::XQueryPointer(
+        display as _,
+        root,
+        &mut root_ret,
+        &mut child_ret,
+        &mut root_x,
+        &mut root_y,
+        &mut win_x,
+        &mut win_y,
+        &mut mask,
+    );
+
+    if on_screen == xlib::False {
+        return None;
+    }
+
+    Some((root_x, root_y))
+}
+
+// Read a window property made up of 32-bit values (windows, atoms, cardinals).
+// Returns an empty vec if the property doesn't exist or isn't the expected type.
+pub unsafe fn get_property_longs(
+    display: *mut c_void,
+    window: xlib::Window,
+    property: &str,
+    property_type: xlib::Atom,
+) -> Vec<c_ulong> {
+    let mut actual_type: xlib::Atom = 0;
+    let mut actual_format: i32 = 0;
+    let mut item_count: c_ulong = 0;
+    let mut bytes_after: c_ulong = 0;
+    let mut data: *mut c_uchar = ptr::null_mut();
+
+    let status = xlib::XGetWindowProperty(
+        display as _,
+        window,
+        intern_atom(display, property),
+        0,
+        4096,
+        xlib::False,
+        property_type,
+        &mut actual_type,
+        &mut actual_format,
+        &mut item_count,
+        &mut bytes_after,
+        &mut data,
+    );
+
+    if status != xlib::Success as i32 || data.is_null() {
+        return vec![];
+    }
+
+    // Xlib hands back 32-bit properties as an array of C longs.
+    let values = if actual_format == 32 {
+        std::slice::from_raw_parts(data as *const c_ulong, item_count as usize).to_vec()
+    } else {
+        vec![]
+    };
+
+    xlib::XFree(data as _);
+    values
+}
+
+// The position and size of the focused window, using `_NET_ACTIVE_WINDOW`.
+pub unsafe fn active_window_rect(display: *mut c_void) -> Option<(i32, i32, i32, i32)> {
+    let root = xlib::XDefaultRootWindow(display as _);
+    let window = match get_property_longs(display, root, "_NET_ACTIVE_WINDOW", xlib::XA_WINDOW).first() {
+        Some(&window) if window != 0 => window,
+        _ => return None,
+    };
+
+    let mut attributes = std::mem::MaybeUninit::<xlib::XWindowAttributes>::uninit();
+    if xlib::XGetWindowAttributes(display as _, window, attributes.as_mut_ptr()) == 0 {
+        return None;
+    }
+    let attributes = attributes.assume_init();
+
+    // Window positions are relative to their parent, which is usually a window manager frame.
+    let (mut x, mut y) = (0, 0);
+    let mut child: xlib::Window = 0;
+    xlib::XTranslateCoordinates(display as _, window, root, 0, 0, &mut x, &mut y, &mut child);
+
+    Some((x, y, attributes.width, attributes.height))
+}
+
+// Find a top-level client window belonging to a process, using `_NET_WM_PID`.
+// This is best-effort; not every application sets `_NET_WM_PID`.
+pub unsafe fn find_window_by_pid(display: *mut c_void, pid: u32) -> Option<xlib::Window> {
+    let root = xlib::XDefaultRootWindow(display as _);
+    let clients = get_property_longs(display, root, "_NET_CLIENT_LIST", xlib::XA_WINDOW);
+
+    clients.into_iter().find(|&window| {
+        get_property_longs(display, window, "_NET_WM_PID", xlib::XA_CARDINAL).first() == Some(&(pid as c_ulong))
+    })
+}
+
+// Find the keycode for a keysym name, e.g. "XF86AudioPlay", in the current keyboard layout.
+pub unsafe fn keycode_for(display: *mut c_void, name: &str) -> Option<xlib::KeyCode> {
+    let name = CString::new(name).ok()?;
+    let keysym = xlib::XStringToKeysym(name.as_ptr());
+    if keysym == 0 {
+        return None;
+    }
+
+    match xlib::XKeysymToKeycode(display as _, keysym) {
+        0 => None,
+        keycode => Some(keycode),
+    }
+}
+
+// Grab a key on the root window, so that we get it whichever window is focused, and other clients
+// don't.  Modifiers are ignored.
+pub unsafe fn grab_key(display: *mut c_void, keycode: xlib::KeyCode) {
+    let root = xlib::XDefaultRootWindow(display as _);
+    xlib::XGrabKey(
+        display as _,
+        keycode as i32,
+        xlib::AnyModifier,
+        root,
+        xlib::True,
+        xlib::GrabModeAsync,
+        xlib::GrabModeAsync,
+    );
+    xlib::XFlush(display as _);
+}
+
+pub unsafe fn ungrab_key(display: *mut c_void, keycode: xlib::KeyCode) {
+    let root = xlib::XDefaultRootWindow(display as _);
+    xlib::XUngrabKey(display as _, keycode as i32, xlib::AnyModifier, root);
+    xlib::XFlush(display as _);
+}
+
+// Ask the window manager to raise and focus a window.
+pub unsafe fn activate_window(display: *mut c_void, window: xlib::Window) {
+    let root = xlib::XDefaultRootWindow(display as _);
+
+    let mut data = xlib::ClientMessageData::new();
+    // Source indication 2 means the request came from a direct user action, which window managers
+    // are less likely to ignore.
+    data.set_long(0, 2);
+    data.set_long(1, xlib::CurrentTime as c_long);
+
+    let event = xlib::XClientMessageEvent {
+        type_: xlib::ClientMessage,
+        serial: 0,
+        send_event: xlib::True,
+        display: display as _,
+        window,
+        message_type: intern_atom(display, "_NET_ACTIVE_WINDOW"),
+        format: 32,
+        data,
+    };
+
+    let mut event = xlib::XEvent::from(event);
+    xlib::XSendEvent(
+        display as _,
+        root,
+        xlib::False,
+        xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+        &mut event,
+    );
+    xlib::XFlush(display as _);
+}